@@ -2,5 +2,7 @@ extern crate winres;
 fn main() {
     let mut res = winres::WindowsResource::new();
     res.set_icon_with_id("res/blackout.ico", "icon");
+    res.set_icon_with_id("res/blackout-armed.ico", "icon-armed");
+    res.set_icon_with_id("res/blackout-busy.ico", "icon-busy");
     res.compile().unwrap();
 }
\ No newline at end of file
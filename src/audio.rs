@@ -11,6 +11,8 @@ pub const SOUND_TRIGGER: &[u8] = include_bytes!("../res/trigger.mp3");
 pub const SOUND_ERROR: &[u8] = include_bytes!("../res/error.mp3");
 pub const SOUND_HEARTBEAT: &[u8] = include_bytes!("../res/heartbeat.mp3");
 pub const SOUND_SUCCESS: &[u8] = include_bytes!("../res/success.mp3");
+/// a partial success is announced by the success sound followed by the error one
+pub const SOUND_PARTIAL: &[&[u8]] = &[SOUND_SUCCESS, SOUND_ERROR];
 
 pub type Player = Arc<Soloud>;
 
@@ -27,6 +29,13 @@ pub async fn play_sound(sl: Player, data: &[u8]) {
     }
 }
 
+/// plays the sounds one after the other
+pub async fn play_sounds(sl: Player, sounds: &[&[u8]]) {
+    for data in sounds {
+        play_sound(sl.clone(), data).await;
+    }
+}
+
 pub async fn heartbeat(sl: Player, tick: Duration, terminate: Arc<AtomicBool>) {
     loop {
         play_sound(sl.clone(), SOUND_HEARTBEAT).await;
@@ -1,29 +1,250 @@
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs::{copy, create_dir_all, File, metadata, OpenOptions};
-use std::io::{BufRead, BufReader};
+use std::fs::{create_dir_all, File, hard_link, metadata, Metadata, OpenOptions, remove_file, symlink_metadata};
+use std::io;
+use std::io::{BufRead, BufReader, Read};
 use std::io::Write;
-use std::path::{Path, PathBuf};
-use std::sync::{Arc, mpsc};
+use std::path::{absolute, Component, Path, PathBuf, Prefix};
+use std::sync::{Arc, Condvar, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::Sender;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
 use chksum_hash_md5 as md5;
 use chrono::Local;
+use crossbeam::channel::{Sender, unbounded};
 use glob::glob;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use sysinfo::Disks;
 use walkdir::WalkDir;
 
-use crate::{audio, tokio};
-use crate::audio::{heartbeat, play_sound, SOUND_ERROR, SOUND_SUCCESS};
+use crate::{audio, config, tokio};
+use crate::audio::{heartbeat, play_sound, play_sounds, SOUND_CANCEL, SOUND_ERROR, SOUND_PARTIAL, SOUND_SUCCESS};
+use crate::config::SourceEntry;
 use crate::echo::echo_main;
+use crate::filters::glob_root;
 use crate::logger::{error, info};
+use crate::manifest;
+use crate::manifest::{Journal, Manifest, ManifestEntry, Outcome, Permissions};
+use crate::retention;
+use crate::state::{APP_STATE, ApplicationState, BackupProgress, BackupResult};
 use crate::TOKIO;
+use crate::usage::UsageMeter;
+use crate::verify::verify_entries;
+
+/// a file found in the sources
+pub struct SourceFile {
+    /// absolute path
+    pub path: PathBuf,
+    pub size: u64,
+    /// modification time, in seconds since the unix epoch
+    pub modified: u64,
+    pub permissions: Permissions,
+}
+
+/// a file that could not be backed up
+pub struct FailedFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub error: String,
+}
+
+/// per file results of a backup
+#[derive(Default)]
+pub struct BackupReport {
+    pub snapshot: String,
+    /// the log written next to the snapshot
+    pub log: PathBuf,
+    pub copied: usize,
+    pub unchanged: usize,
+    /// stored by the interrupted backup that this one resumed
+    pub resumed: usize,
+    pub written_size: u64,
+    pub unchanged_size: u64,
+    pub resumed_size: u64,
+    pub failed: Vec<FailedFile>,
+    /// the backup was cancelled before all the files were copied
+    pub cancelled: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupOutcome {
+    Success,
+    /// some files could not be backed up
+    PartialSuccess,
+    Failure,
+}
+
+impl BackupReport {
+    /// files whose content is in the snapshot
+    pub fn stored(&self) -> usize {
+        self.copied + self.unchanged + self.resumed
+    }
+
+    pub fn outcome(&self) -> BackupOutcome {
+        if self.failed.is_empty() {
+            BackupOutcome::Success
+        } else if self.stored() > 0 {
+            BackupOutcome::PartialSuccess
+        } else {
+            BackupOutcome::Failure
+        }
+    }
+}
+
+impl SourceFile {
+    fn new(path: PathBuf, metadata: &Metadata) -> Self {
+        let modified = metadata.modified().ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        Self {
+            path: absolute(&path).unwrap_or(path),
+            size: metadata.len(),
+            modified,
+            permissions: metadata.permissions().into()
+        }
+    }
+}
+
+/// the progress in the application state is updated at most this often, its subscribers wake up for each update
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// asks a backup to stop, it's checked between the files so the current one is completed
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+lazy_static! {
+    /// token of the running backup, none when no backup is running, with the condition notified when it ends
+    static ref ACTIVE: (Mutex<Option<CancellationToken>>, Condvar) = (Mutex::new(None), Condvar::new());
+}
+
+pub fn is_running() -> bool {
+    APP_STATE.read().is_backup()
+}
+
+/// cancels the running backup, if any, returns whether there was one
+pub fn cancel() -> bool {
+    match ACTIVE.0.lock().unwrap().as_ref() {
+        Some(token) => {
+            info!("backup", "Backup cancellation requested.");
+            token.cancel();
+            true
+        },
+        None => false
+    }
+}
+
+/// cancels the running backup and waits until it ends, up to the timeout
+/// used when quitting, since the application state doesn't tell anymore whether a backup is running
+pub fn cancel_and_wait(timeout: Duration) {
+    if !cancel() {
+        return;
+    }
+    let (active, ended) = &*ACTIVE;
+    let (_active, wait) = ended.wait_timeout_while(active.lock().unwrap(), timeout, |token| token.is_some()).unwrap();
+    if wait.timed_out() {
+        error!("backup", "The cancelled backup did not stop in time!");
+    }
+}
+
+/// the progress of a phase of the backup, published to the application state at intervals
+struct ProgressReporter {
+    progress: BackupProgress,
+    verifying: bool,
+    published: Instant,
+}
+
+impl ProgressReporter {
+    /// the application state must already be in the phase
+    fn new(profile: &str, total_files: usize, total_size: u64, verifying: bool) -> Self {
+        let progress = BackupProgress { profile: profile.to_string(), total_files, total_size, ..Default::default() };
+        let mut reporter = Self { progress, verifying, published: Instant::now() };
+        reporter.publish(true);
+        reporter
+    }
+
+    fn start(&mut self, path: &Path) {
+        self.progress.current = Some(path.to_path_buf());
+        self.publish(false);
+    }
+
+    fn done(&mut self, size: u64) {
+        self.progress.files += 1;
+        self.progress.size += size;
+        self.publish(self.progress.files == self.progress.total_files);
+    }
+
+    fn publish(&mut self, force: bool) {
+        if !force && self.published.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        self.published = Instant::now();
+        let progress = self.progress.clone();
+        APP_STATE.update(if self.verifying { ApplicationState::Verifying(progress) } else { ApplicationState::BackingUp(progress) });
+    }
+}
+
+/// ends the backup phase in the application state if the backup panics, and releases the cancellation token
+struct BackupGuard {
+    profile: String,
+    token: CancellationToken,
+}
+
+impl BackupGuard {
+    /// clears the token of this backup, a backup started after it ended keeps its own
+    fn release(&self) {
+        let (active, ended) = &*ACTIVE;
+        let mut active = active.lock().unwrap();
+        if active.as_ref().is_some_and(|token| Arc::ptr_eq(&token.0, &self.token.0)) {
+            *active = None;
+            ended.notify_all();
+        }
+    }
+}
+
+impl Drop for BackupGuard {
+    fn drop(&mut self) {
+        self.release();
+        // the backup finished on its own otherwise, and a new one may be running already
+        if thread::panicking() {
+            APP_STATE.finish(BackupResult {
+                profile: self.profile.clone(),
+                snapshot: None,
+                outcome: BackupOutcome::Failure,
+                message: "Backup thread panicked!".to_string(),
+                finished: Local::now().to_rfc3339(),
+                log: None
+            });
+        }
+    }
+}
+
+/// only one backup at a time, whoever triggers it
+pub fn backup_main(profile: String) -> BackupOutcome {
+    if let Err(e) = APP_STATE.change(ApplicationState::BackingUp(BackupProgress { profile: profile.clone(), ..Default::default() })) {
+        error!("backup", format!("Backup of profile {} refused, {}!", profile, e));
+        return BackupOutcome::Failure;
+    }
+    let token = CancellationToken::default();
+    let guard = BackupGuard { profile: profile.clone(), token: token.clone() };
+    *ACTIVE.0.lock().unwrap() = Some(token.clone());
+    let mut meter = UsageMeter::new();
 
-pub fn backup_main() {
     // we can ignore the errors because ui is non critical for the backup operation
-    let (tx, rx) = mpsc::channel();
+    let (tx, rx) = unbounded();
 
     let echo = thread::spawn(move || {
         echo_main(rx);
@@ -35,133 +256,412 @@ pub fn backup_main() {
     tx.send("█  ████  ██  ████████        ██  ████  ██  ███  ███  ████  ██  ████  █████  ████".to_string()).unwrap();
     tx.send("█       ███        ██  ████  ███      ███  ████  ███      ████      ██████  ████\n".to_string()).unwrap();
 
-    let msg = "Parsing sources...".to_string();
+    let msg = format!("Profile: {}. Parsing sources...", profile);
     info!("backup", msg.clone());
     tx.send(msg).unwrap();
 
     let heartbeat_stop = Arc::new(AtomicBool::new(false));
-    tokio!().spawn(heartbeat(audio::PLAYER.clone(), Duration::from_secs(1), heartbeat_stop.clone()));
+    let heartbeat_interval = Duration::from_millis(config::get().audio.heartbeat_interval_ms);
+    tokio!().spawn(heartbeat(audio::PLAYER.clone(), heartbeat_interval, heartbeat_stop.clone()));
 
+    let mut outcome = BackupOutcome::Success;
     let mut error_msg = String::default();
-    match parse_sources() {
+    let mut summary = String::default();
+    let mut snapshot = None;
+    let mut log = None;
+    let parsed = parse_sources(&profile, &token);
+    meter.lap("parse");
+    match parsed {
+        Err(_) if token.is_cancelled() => {
+            outcome = BackupOutcome::Failure;
+            let msg = "Backup cancelled while parsing the sources.".to_string();
+            error!("backup", msg.clone());
+            summary = msg.clone();
+            tx.send(msg).unwrap();
+            tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_CANCEL));
+        },
         Err(e) => { error_msg = format!("Error parsing resources! {}", e); },
-        Ok((paths, size, checksum)) => {
-            if paths.len() > 0 {
-                let suitable_mounts = find_suitable_mounts(size);
+        Ok(ParsedSources { files, size, checksum, warnings }) => {
+            let msg = format!("Found {} files ({}) in the sources, {} entries skipped.", files.len(), size.human_readable(), warnings.len());
+            info!("backup", msg.clone());
+            tx.send(msg).unwrap();
+            for warning in warnings.iter() {
+                error!("backup", warning.clone());
+                tx.send(format!("  {}", warning)).unwrap();
+            }
+
+            if files.len() > 0 {
+                let (mut suitable_mounts, mut smallest_required) = find_suitable_mounts(&files, &profile, &checksum);
+                if suitable_mounts.is_empty() && config::get().destination(&profile).retention.is_set() {
+                    let msg = "No drive has enough free space, applying the retention to make room...".to_string();
+                    info!("backup", msg.clone());
+                    tx.send(msg).unwrap();
+                    // one drive at a time, the others keep their snapshots
+                    for mount_point in prune_candidates(&files, &profile, &checksum) {
+                        apply_retention(&mount_point, &profile, &tx);
+                        (suitable_mounts, smallest_required) = find_suitable_mounts(&files, &profile, &checksum);
+                        if !suitable_mounts.is_empty() {
+                            break;
+                        }
+                    }
+                }
+                meter.lap("discovery");
 
                 if suitable_mounts.len() == 0 {
-                    error_msg = format!("No removable drives with enough free space found! Required space: {}", size.human_readable());
+                    // the least any drive needs, the whole sources when there is no drive at all
+                    error_msg = format!("No removable drives with enough free space found! Required space: {}", smallest_required.unwrap_or(size).human_readable());
                 } else {
-                    let (dest, available_space) = &suitable_mounts[0];
+                    let (dest, available_space, required_space) = &suitable_mounts[0];
 
-                    let msg = format!("Found {} as suitable destination drive. Available space: {}. Required space {}. Started copying files...",
-                                      dest.to_string_lossy(), available_space.human_readable(), size.human_readable());
+                    let msg = format!("Found {} as suitable destination drive. Available space: {}. Required space {} (sources total {}). Started copying files...",
+                                      dest.to_string_lossy(), available_space.human_readable(), required_space.human_readable(), size.human_readable());
                     info!("backup", msg.clone());
                     tx.send(msg).unwrap();
 
-                    match copy_files(&paths, dest, checksum, size, tx.clone()) {
+                    match copy_files(&files, dest, CopyContext {
+                        profile: &profile,
+                        checksum,
+                        total_size: size,
+                        warnings: &warnings,
+                        meter: &mut meter,
+                        token: &token
+                    }, tx.clone()) {
                         Err(e) => {
                             error_msg = format!("Error copying files! {}", e);
                         },
-                        Ok(_) => {
-                            tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_SUCCESS));
+                        Ok(report) if report.cancelled => {
+                            outcome = BackupOutcome::Failure;
+                            snapshot = Some(report.snapshot.clone());
+                            log = Some(report.log.clone());
+                            let msg = format!("Backup {} cancelled, {} files were stored.", report.snapshot, report.stored());
+                            error!("backup", msg.clone());
+                            summary = msg.clone();
+                            tx.send(msg).unwrap();
+                            tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_CANCEL));
+                        },
+                        Ok(report) => {
+                            outcome = report.outcome();
+                            snapshot = Some(report.snapshot.clone());
+                            log = Some(report.log.clone());
+                            match outcome {
+                                BackupOutcome::Success => {
+                                    let msg = format!("Backup {} completed successfully.", report.snapshot);
+                                    info!("backup", msg.clone());
+                                    summary = msg.clone();
+                                    tx.send(msg).unwrap();
+                                    tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_SUCCESS));
+                                    apply_retention(dest, &profile, &tx);
+                                },
+                                BackupOutcome::PartialSuccess => {
+                                    let msg = format!("Backup {} partially completed, {} files failed!", report.snapshot, report.failed.len());
+                                    error!("backup", msg.clone());
+                                    summary = msg.clone();
+                                    tx.send(msg).unwrap();
+                                    tokio!().spawn(play_sounds(audio::PLAYER.clone(), SOUND_PARTIAL));
+                                },
+                                BackupOutcome::Failure => {
+                                    error_msg = format!("Backup {} failed, none of the files could be backed up!", report.snapshot);
+                                }
+                            }
                         }
                     }
                 }
+            } else {
+                error_msg = "No files to back up!".to_string();
             }
         }
     }
 
     if !error_msg.is_empty() {
+        outcome = BackupOutcome::Failure;
         error!("backup", error_msg.clone());
+        summary = error_msg.clone();
         tx.send(error_msg).unwrap();
         tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_ERROR));
     }
 
     heartbeat_stop.store(true, Ordering::Release);
 
+    info!("backup", "Resources used:");
+    for line in meter.report() {
+        info!("backup", line);
+    }
+
     let msg = "Backup finished.".to_string();
     info!("backup", msg.clone());
     tx.send(msg).unwrap();
 
-    thread::sleep(Duration::from_secs(5)); // give user some time to view the console
+    if APP_STATE.read() != ApplicationState::Quit {
+        thread::sleep(Duration::from_secs(config::get().ui.linger_secs)); // give user some time to view the console
+    }
 
     drop(tx); // close echo
 
     echo.join()
         .inspect_err(|_e| { error!("backup", "Echo thread panicked!"); })
         .err();
+
+    // released before finishing, as soon as the state is finished another backup can start
+    guard.release();
+    APP_STATE.finish(BackupResult {
+        profile,
+        snapshot,
+        outcome,
+        message: summary,
+        finished: Local::now().to_rfc3339(),
+        log
+    });
+
+    outcome
 }
 
-/// returns (on success) a vector of (pathbuf, size) of files, the total size, and the sources file checksum
-fn parse_sources() -> Result<(Vec<(PathBuf, u64)>, u64, String), Box<dyn Error>> {
+/// the files found in the sources
+pub struct ParsedSources {
+    pub files: Vec<SourceFile>,
+    /// total size of the files
+    pub size: u64,
+    pub checksum: String,
+    /// problems with single entries, which were skipped
+    pub warnings: Vec<String>,
+}
+
+/// metadata of a file, telling broken symlinks apart from the other errors
+fn read_metadata(path: &Path) -> Result<Metadata, String> {
+    metadata(path).map_err(|e| match symlink_metadata(path) {
+        Ok(link) if link.is_symlink() => format!("Broken symlink: {}", path.display()),
+        _ => format!("Unreadable metadata: {}: {}", path.display(), e)
+    })
+}
+
+/// returns (on success) the files to back up, the total size, the sources checksum and the entries that were skipped
+/// a single unreadable entry never fails the whole backup
+/// a cancelled parsing is an error
+fn parse_sources(profile: &str, token: &CancellationToken) -> Result<ParsedSources, Box<dyn Error>> {
     let mut parsed = Vec::new();
     let mut tot_size: u64 = 0;
+    let mut warnings = Vec::new();
 
-    let file = File::open("sources.txt")?;
-
-    let reader = BufReader::new(file);
+    let config = config::get();
+    let sources = config.sources(profile);
+    let mut entries: Vec<SourceEntry> = sources.paths.iter().map(|path| SourceEntry::new(path)).collect();
+    if let Some(path) = &sources.file {
+        match File::open(path) {
+            Ok(file) => {
+                for (n, line) in BufReader::new(file).lines().enumerate() {
+                    match line {
+                        Ok(line) => {
+                            // blank lines and comments are not sources
+                            let line = line.trim();
+                            if !line.is_empty() && !line.starts_with('#') {
+                                entries.push(SourceEntry::new(line));
+                            }
+                        },
+                        Err(e) => { warnings.push(format!("Unreadable line {} of {}: {}", n + 1, path.display(), e)); }
+                    }
+                }
+            },
+            Err(e) => { warnings.push(format!("Cannot read sources file {}: {}", path.display(), e)); }
+        }
+    }
+    entries.extend(sources.entries.iter().cloned());
 
-    // we parse the file and calculate the has at the same time
+    // we parse the sources and calculate the hash at the same time
     let mut checksum = md5::default();
 
-    for line in reader.lines() {
-        let line = line.unwrap();
+    for entry in entries {
+        if token.is_cancelled() {
+            return Err("cancelled while parsing the sources".into());
+        }
+        let filters = sources.filters(&entry)?;
+        let line = entry.path.trim();
 
-        checksum.update(&line);
+        checksum.update(line);
+        checksum.update(filters.describe());
 
-        let path_pattern = Path::new(&line);
+        let mut add = |file: SourceFile| {
+            if filters.accepts(&file.path, file.size, file.modified) {
+                tot_size += file.size;
+                parsed.push(file);
+            }
+        };
 
-        if path_pattern.to_string_lossy().contains('*') || path_pattern.to_string_lossy().contains('?') {
-            let pattern = line.trim();
-            let paths = glob(pattern)?;
+        if line.contains(['*', '?']) {
+            let root = glob_root(line);
+            let paths = match glob(line) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    warnings.push(format!("Invalid glob pattern {}: {}", line, e));
+                    continue;
+                }
+            };
 
+            let mut matched = false;
             for entry in paths {
+                if token.is_cancelled() {
+                    return Err("cancelled while parsing the sources".into());
+                }
                 match entry {
                     Ok(entry) => {
-                        let size = metadata(&entry).unwrap().len();
-                        tot_size += size;
-                        parsed.push((entry, size));
+                        matched = true;
+                        let metadata = match read_metadata(&entry) {
+                            Ok(metadata) => metadata,
+                            Err(e) => { warnings.push(e); continue; }
+                        };
+                        let relative = entry.strip_prefix(&root).unwrap_or(&entry);
+                        if metadata.is_dir() || filters.excludes_with_parents(relative, false) {
+                            continue;
+                        }
+                        add(SourceFile::new(entry.clone(), &metadata));
                     },
                     Err(e) => {
-                        return Err(e.into());
+                        warnings.push(format!("Unreadable path: {}", e));
                     }
                 }
             }
+            if !matched {
+                warnings.push(format!("No files match: {}", line));
+            }
         } else {
-            let path = Path::new(&line);
-            if path.exists() {
-                if path.is_dir() {
-                    for entry in WalkDir::new(path).into_iter().filter_map(Result::ok) {
-                        let size = metadata(entry.path()).unwrap().len();
-                        parsed.push((PathBuf::from(entry.path()), size));
-                        tot_size += size;
+            let path = Path::new(line);
+            let metadata = match read_metadata(path) {
+                Ok(metadata) => metadata,
+                Err(_) if symlink_metadata(path).is_err() => {
+                    warnings.push(format!("Path does not exist: {}", path.display()));
+                    continue;
+                },
+                Err(e) => { warnings.push(e); continue; }
+            };
+            if metadata.is_dir() {
+                // excluded folders are not walked at all, directories are recreated from the file paths
+                let walker = WalkDir::new(path).into_iter()
+                    .filter_entry(|entry| entry.depth() == 0 || !filters.excludes(entry.path().strip_prefix(path).unwrap_or(entry.path()), entry.file_type().is_dir()));
+                for entry in walker {
+                    if token.is_cancelled() {
+                        return Err("cancelled while parsing the sources".into());
+                    }
+                    let entry = match entry {
+                        Ok(entry) => entry,
+                        Err(e) => {
+                            warnings.push(format!("Unreadable path: {}", e));
+                            continue;
+                        }
+                    };
+                    if entry.file_type().is_dir() {
+                        continue;
+                    }
+                    match read_metadata(entry.path()) {
+                        Ok(metadata) if metadata.is_dir() => {}, // symlinks to folders are not followed
+                        Ok(metadata) => { add(SourceFile::new(PathBuf::from(entry.path()), &metadata)); },
+                        Err(e) => { warnings.push(e); }
                     }
-                } else {
-                    let size = metadata(PathBuf::from(path)).unwrap().len();
-                    parsed.push((PathBuf::from(path), size));
-                    tot_size += size;
                 }
             } else {
-                return Err(format!("Path does not exist: {}", path.display()).into());
+                let name = Path::new(path.file_name().unwrap_or_default());
+                if !filters.excludes(name, false) {
+                    add(SourceFile::new(PathBuf::from(path), &metadata));
+                }
             }
         }
     }
 
-    return Ok((parsed, tot_size, checksum.digest().to_hex_lowercase()));
+    Ok(ParsedSources {
+        files: parsed,
+        size: tot_size,
+        checksum: checksum.digest().to_hex_lowercase(),
+        warnings
+    })
+}
+
+/// deletes the snapshots of a profile on a drive that its retention doesn't keep, the backup goes on whatever happens
+fn apply_retention(mount_point: &Path, profile: &str, ui: &Sender<String>) {
+    if !config::get().destination(profile).retention.is_set() {
+        return;
+    }
+    let msg = match retention::prune(&backup_root(mount_point, profile), profile, false) {
+        Ok((decisions, freed)) => {
+            let deleted = decisions.iter().filter(|decision| decision.keep.is_none()).count();
+            if deleted == 0 {
+                return;
+            }
+            let msg = format!("Retention: deleted {} old snapshots, freeing about {}.", deleted, freed.human_readable());
+            info!("backup", msg.clone());
+            msg
+        },
+        Err(e) => {
+            let msg = format!("Retention failed! {}", e);
+            error!("backup", msg.clone());
+            msg
+        }
+    };
+    ui.send(msg).unwrap();
 }
 
-fn find_suitable_mounts(size: u64) -> Vec<(PathBuf, u64)> {
+/// folder holding the snapshots of a profile in a destination drive
+pub fn backup_root(mount_point: &Path, profile: &str) -> PathBuf {
+    mount_point.join(&config::get().destination(profile).folder)
+}
+
+/// returns (mount point, available space, required space) of the removable drives that can store the backup,
+/// along with the smallest required space of all the removable drives
+/// the required space only accounts for the files that changed since the latest snapshot on each drive (when the
+/// backups are incremental), and that are not stored yet by an interrupted backup of the same sources, whose drives come first
+fn find_suitable_mounts(files: &Vec<SourceFile>, profile: &str, checksum: &str) -> (Vec<(PathBuf, u64, u64)>, Option<u64>) {
     let disks = Disks::new_with_refreshed_list();
-    let suitable = disks.list().into_iter().filter(|disk| disk.is_removable() && size < disk.available_space());
     let mut mount_points = Vec::new();
-    for disk in suitable {
-        mount_points.push((disk.mount_point().to_owned(), disk.available_space()));
+    let mut smallest_required = None;
+    for disk in disks.list().into_iter().filter(|disk| disk.is_removable()) {
+        let (required_space, resumable) = required_space(files, &backup_root(disk.mount_point(), profile), profile, checksum);
+        smallest_required = Some(smallest_required.map_or(required_space, |smallest: u64| smallest.min(required_space)));
+        if required_space < disk.available_space() {
+            let mount_point = (disk.mount_point().to_owned(), disk.available_space(), required_space);
+            if resumable {
+                mount_points.insert(0, mount_point);
+            } else {
+                mount_points.push(mount_point);
+            }
+        }
     }
-    mount_points
+    (mount_points, smallest_required)
 }
 
-trait HumanReadable {
+/// space the backup needs in a backup folder, and whether it holds an interrupted snapshot to resume
+fn required_space(files: &Vec<SourceFile>, backup_root: &Path, profile: &str, checksum: &str) -> (u64, bool) {
+    // without incremental backups every file is copied again, whatever the previous snapshot holds
+    let previous = if config::get().destination(profile).incremental { manifest::latest(backup_root, profile) } else { None };
+    let resumable = manifest::resumable(backup_root, profile, checksum);
+    (delta_size(files, previous.iter().chain(resumable.iter()).collect()), resumable.is_some())
+}
+
+/// the removable drives where applying the retention would make enough room for the backup,
+/// the ones that would have the most space left first
+fn prune_candidates(files: &Vec<SourceFile>, profile: &str, checksum: &str) -> Vec<PathBuf> {
+    let disks = Disks::new_with_refreshed_list();
+    let mut candidates = Vec::new();
+    for disk in disks.list().into_iter().filter(|disk| disk.is_removable()) {
+        let backup_root = backup_root(disk.mount_point(), profile);
+        if !backup_root.is_dir() {
+            continue;
+        }
+        let Ok((_, freed)) = retention::prune(&backup_root, profile, true) else { continue; };
+        let (required_space, _) = required_space(files, &backup_root, profile, checksum);
+        if let Some(left) = (disk.available_space() + freed).checked_sub(required_space).filter(|left| *left > 0) {
+            candidates.push((disk.mount_point().to_owned(), left));
+        }
+    }
+    candidates.sort_by(|a, b| b.1.cmp(&a.1));
+    candidates.into_iter().map(|(mount_point, _)| mount_point).collect()
+}
+
+/// total size of the files that are in none of the snapshots with the same size and modification time
+fn delta_size(files: &Vec<SourceFile>, snapshots: Vec<&Manifest>) -> u64 {
+    let stored: Vec<HashMap<&Path, &ManifestEntry>> = snapshots.into_iter().map(Manifest::by_source).collect();
+    files.iter()
+        .filter(|file| !stored.iter().any(|stored| stored.get(file.path.as_path()).is_some_and(|entry| entry.size == file.size && entry.modified == file.modified)))
+        .map(|file| file.size)
+        .sum()
+}
+
+pub trait HumanReadable {
     fn human_readable(&self) -> String;
 }
 
@@ -200,60 +700,423 @@ impl HumanReadable for Duration {
     }
 }
 
-fn copy_files(files: &Vec<(PathBuf, u64)>, dest: &PathBuf, checksum: String, total_size: u64, ui: Sender<String>) -> Result<(), Box<dyn Error>> {
-    let timestamp = Local::now().format("%Y%m%d_%H%M%S").to_string();
-    let backup_root = dest.join(PathBuf::from("BlackoutBackup"));
-    let backup_path = backup_root.join(format!("{}", timestamp));
+/// what the backup found out before copying, along with the means to measure and cancel the copy
+struct CopyContext<'a> {
+    profile: &'a str,
+    checksum: String,
+    total_size: u64,
+    /// the sources that were skipped, listed in the log
+    warnings: &'a Vec<String>,
+    meter: &'a mut UsageMeter,
+    token: &'a CancellationToken,
+}
+
+fn copy_files(files: &Vec<SourceFile>, dest: &PathBuf, context: CopyContext, ui: Sender<String>) -> Result<BackupReport, Box<dyn Error>> {
+    let CopyContext { profile, checksum, total_size, warnings, meter, token } = context;
+    let backup_root = backup_root(dest, profile);
+    let config = config::get();
+    let destination = config.destination(profile);
+
+    // an interrupted snapshot of the same sources is continued instead of starting over,
+    // a cancelled one goes back to its staging name first
+    let resumed = manifest::resumable(&backup_root, profile, &checksum);
+    if let Some(resumed) = &resumed {
+        manifest::reopen(&backup_root, &resumed.snapshot)?;
+    }
+
+    // the profile is part of the snapshot name
+    let timestamp = match &resumed {
+        Some(resumed) => resumed.snapshot.clone(),
+        None => format!("{}_{}", Local::now().format("%Y%m%d_%H%M%S"), profile)
+    };
+    // written under the staging name, renamed into place when complete
+    let staging = manifest::staging(&timestamp);
+    let backup_path = backup_root.join(&staging);
+
+    // the previous snapshot of the same profile is read before creating the new one
+    let previous = if destination.incremental { manifest::latest(&backup_root, profile) } else { None };
+    let previous_entries = previous.as_ref().map(Manifest::by_source).unwrap_or_default();
+
+    // leftovers of backups that were interrupted before their commit, they are not snapshots
+    let partials: Vec<String> = manifest::partials(&backup_root).into_iter().filter(|partial| *partial != timestamp).collect();
 
     create_dir_all(&backup_path)?;
 
-    let mut backup_log = OpenOptions::new().write(true).create(true).open(backup_root.join(format!("{}.log", timestamp)))?;
+    // a resumed snapshot goes on with its log, failing to write it (e.g. the drive was removed or is full)
+    // stops the backup, the journal lets the next one resume
+    let mut backup_log = OpenOptions::new().append(true).create(true).open(manifest::log_path(&backup_root, &staging))?;
 
-    writeln!(backup_log, "Sources checksum: {}\n", checksum).unwrap();
+    let mut resumed_entries = resumed.as_ref().map(|resumed| resumed.entries.clone()).unwrap_or_default();
+    if let Some(resumed) = &resumed {
+        // the last recorded copy is the likeliest to be damaged by the interruption, it's read back before trusting it
+        if let Some(last) = resumed_entries.last().filter(|entry| entry.outcome == Outcome::Copied) {
+            let path = backup_path.join(&last.path);
+            if hash_file(&path).ok() != last.hash {
+                let msg = format!("The last file stored before the interruption is damaged, {} is copied again.", last.source.to_string_lossy());
+                writeln!(backup_log, "\n{}", msg)?;
+                error!("backup", msg.clone());
+                ui.send(msg).unwrap();
+                resumed_entries.pop();
+            }
+        }
+        let msg = format!("Resuming snapshot {} started at {}, {} files are already stored.", resumed.snapshot, resumed.started, resumed_entries.len());
+        writeln!(backup_log, "\n{}\n", msg)?;
+        info!("backup", msg.clone());
+        ui.send(msg).unwrap();
+    }
+    let resumed_entries: HashMap<&Path, &ManifestEntry> = resumed_entries.iter().filter(|entry| entry.is_stored() && entry.verified != Some(false)).map(|entry| (entry.source.as_path(), entry)).collect();
 
-    let mut written_size: u64 = 0;
+    writeln!(backup_log, "Profile: {}\n", profile)?;
+    writeln!(backup_log, "Sources checksum: {}\n", checksum)?;
+    if let Some(previous) = &previous {
+        writeln!(backup_log, "Previous snapshot: {}\n", previous.snapshot)?;
+    }
+    for partial in partials.iter() {
+        let msg = format!("Snapshot {} was interrupted before completing, its files are left in {}.", partial, manifest::staging(partial));
+        writeln!(backup_log, "{}\n", msg)?;
+        error!("backup", msg.clone());
+        ui.send(msg).unwrap();
+    }
+    if !warnings.is_empty() {
+        writeln!(backup_log, "Skipped sources:")?;
+        for warning in warnings {
+            writeln!(backup_log, "{}", warning)?;
+        }
+        writeln!(backup_log)?;
+    }
+
+    let mut manifest = Manifest::new(timestamp.clone(), profile.to_string(), checksum.clone());
+    if let Some(resumed) = &resumed {
+        manifest.started = resumed.started.clone();
+    }
+    let mut journal = Journal::create(&manifest::journal_path(&backup_root, &timestamp), &manifest)?;
+    // the resumed files that are verified with the ones copied now
+    let mut resumed_paths = HashSet::new();
+
+    let mut report = BackupReport {
+        snapshot: timestamp.clone(),
+        log: manifest::log_path(&backup_root, &timestamp),
+        ..Default::default()
+    };
 
     let start = Instant::now();
+    let mut progress = ProgressReporter::new(profile, files.len(), total_size, false);
 
-    for (file, size) in files {
-        // change drive in path
-        let path_no_drive = file.strip_prefix(file.ancestors().last().unwrap()).unwrap();
-        let dest_path = backup_path.join(path_no_drive);
+    for file in files {
+        if token.is_cancelled() {
+            let msg = "Backup cancelled, the remaining files are left out.".to_string();
+            writeln!(backup_log, "\n{}", msg)?;
+            info!("backup", msg.clone());
+            ui.send(msg).unwrap();
+            report.cancelled = true;
+            manifest.incomplete = true;
+            break;
+        }
+
+        // keep the drive in the path so that files from different roots don't collide
+        let (layout_root, original_root, relative_path) = snapshot_layout(&file.path);
+        manifest.roots.entry(layout_root.to_string_lossy().to_string()).or_insert(original_root);
+        let snapshot_path = layout_root.join(relative_path);
+        let dest_path = backup_path.join(&snapshot_path);
+
+        // stored before the interruption and not modified since
+        if let Some(entry) = resumed_entries.get(file.path.as_path()).filter(|entry| entry.size == file.size && entry.modified == file.modified) {
+            report.resumed += 1;
+            report.resumed_size += file.size;
+            if entry.outcome == Outcome::Copied {
+                resumed_paths.insert(entry.path.clone());
+            }
+            journal.append(entry).ok();
+            manifest.entries.push((*entry).clone());
+            progress.start(&file.path);
+            progress.done(file.size);
+            continue;
+        }
 
-        let msg = format!("Copying {} ...", file.to_string_lossy());
+        let msg = format!("Copying {} ...", file.path.to_string_lossy());
         info!("backup", msg.clone());
         ui.send(msg).unwrap();
+        progress.start(&file.path);
 
-        if let Some(parent) = dest_path.parent() {
-            // don't block the entire backup if one file fails
-            create_dir_all(parent).inspect_err(|e| {
-                let msg = format!("{e}");
-                error!("backup", msg.clone());
-                ui.send(msg).unwrap();
-            }).err();
-            copy(file, dest_path).inspect_err(|e| {
-                let msg = format!("{e}");
+        let stored = create_dir_all(dest_path.parent().unwrap()).and_then(|_| {
+            // whatever the interruption left is unlinked, never written through: it can be a hard link into another snapshot
+            if resumed.is_some() && symlink_metadata(&dest_path).is_ok() {
+                remove_file(&dest_path)?;
+            }
+            // a file is unchanged if size, modification time and content match the previous snapshot
+            let unchanged = previous_entries.get(file.path.as_path())
+                .filter(|entry| entry.size == file.size && entry.modified == file.modified)
+                .and_then(|entry| hash_file(&file.path).ok().filter(|hash| entry.hash.as_ref() == Some(hash)).map(|hash| (entry, hash)));
+
+            match unchanged {
+                Some((entry, hash)) => {
+                    Ok((hash, reuse_previous(entry, &previous.as_ref().unwrap().snapshot, &backup_root, &dest_path)))
+                },
+                None => {
+                    copy_hashed(&file.path, &dest_path)
+                        .and_then(|hash| file.permissions.apply(&dest_path).map(|_| (hash, Outcome::Copied)))
+                }
+            }
+        });
+
+        let mut entry = ManifestEntry {
+            path: snapshot_path.clone(),
+            source: file.path.clone(),
+            size: file.size,
+            modified: file.modified,
+            permissions: file.permissions.clone(),
+            hash: None,
+            outcome: Outcome::Copied,
+            verified: None
+        };
+
+        // don't block the entire backup if one file fails
+        match stored {
+            Err(e) => {
+                let msg = format!("Error copying {}: {}", file.path.to_string_lossy(), e);
                 error!("backup", msg.clone());
                 ui.send(msg).unwrap();
-            }).err();
 
-            written_size += size;
+                entry.outcome = Outcome::Failed { error: e.to_string() };
+                report.failed.push(FailedFile {
+                    path: file.path.clone(),
+                    size: file.size,
+                    error: e.to_string()
+                });
+            },
+            Ok((hash, outcome)) => {
+                let note = if outcome == Outcome::Copied {
+                    report.copied += 1;
+                    report.written_size += file.size;
+                    ""
+                } else {
+                    report.unchanged += 1;
+                    report.unchanged_size += file.size;
+                    " (unchanged)"
+                };
+
+                writeln!(backup_log, "{:>10} | {}{}", file.size.human_readable(), snapshot_path.to_string_lossy(), note)?;
+
+                entry.hash = Some(hash);
+                entry.outcome = outcome;
+            }
+        }
 
-            writeln!(backup_log, "{:>10} | {}", size.human_readable(), path_no_drive.to_string_lossy()).unwrap();
+        // the journal only speeds up a resume, the backup goes on without it
+        if entry.is_stored() {
+            journal.append(&entry).ok();
         }
+        manifest.entries.push(entry);
+        progress.done(file.size);
     }
 
     let duration = start.elapsed();
+    meter.lap("copy");
 
-    let msg = format!("Written: {} / {}", written_size.human_readable(), total_size.human_readable());
-    writeln!(backup_log, "\n{}", msg.clone()).unwrap();
+    // read back the copied files, the ones from previous snapshots were verified when they were written
+    let verify = destination.verify && !report.cancelled;
+    if verify {
+        let msg = "Verifying written files...".to_string();
+        info!("backup", msg.clone());
+        ui.send(msg).unwrap();
+    }
+
+    let verify_start = Instant::now();
+    let verify_failed = if verify {
+        APP_STATE.change(ApplicationState::Verifying(BackupProgress { profile: profile.to_string(), ..Default::default() })).ok();
+        let copied = manifest.entries.iter().filter(|entry| entry.outcome == Outcome::Copied);
+        let mut progress = ProgressReporter::new(profile, copied.clone().count(), copied.map(|entry| entry.size).sum(), true);
+        let mut last_size = None;
+        let failed = verify_entries(&backup_root, &backup_path, &mut manifest, true, |entry| {
+            if let Some(size) = last_size.replace(entry.size) {
+                progress.done(size);
+            }
+            progress.start(&entry.source);
+        });
+        if let Some(size) = last_size {
+            progress.done(size);
+        }
+        failed
+    } else {
+        Vec::new()
+    };
+    for (path, reason) in verify_failed {
+        if let Some(entry) = manifest.entries.iter_mut().find(|entry| entry.path == path) {
+            let msg = format!("Verification failed for {}: {}", entry.source.to_string_lossy(), reason);
+            error!("backup", msg.clone());
+            ui.send(msg).unwrap();
+
+            if resumed_paths.contains(&path) {
+                report.resumed -= 1;
+                report.resumed_size -= entry.size;
+            } else {
+                report.copied -= 1;
+                report.written_size -= entry.size;
+            }
+            let error = format!("verification failed, {}", reason);
+            report.failed.push(FailedFile {
+                path: entry.source.clone(),
+                size: entry.size,
+                error: error.clone()
+            });
+            // a damaged copy must never be linked or referenced by the next snapshots
+            entry.outcome = Outcome::Failed { error };
+        }
+    }
+    let verify_duration = verify_start.elapsed();
+    if verify {
+        meter.lap("verify");
+    }
+
+    manifest.finished = Local::now().to_rfc3339();
+    manifest.save(&manifest::manifest_path(&backup_root, &staging))?;
+
+    if !report.failed.is_empty() {
+        writeln!(backup_log, "\nFailed files:")?;
+        for failed in report.failed.iter() {
+            writeln!(backup_log, "{:>10} | {} | {}", failed.size.human_readable(), failed.path.to_string_lossy(), failed.error)?;
+        }
+    }
+
+    let msg = format!("Written: {} / {} ({} files)", report.written_size.human_readable(), total_size.human_readable(), report.copied);
+    writeln!(backup_log, "\n{}", msg.clone())?;
+    info!("backup", msg.clone());
+    ui.send(msg).unwrap();
+
+    let msg = format!("Unchanged since the previous snapshot: {} ({} files)", report.unchanged_size.human_readable(), report.unchanged);
+    writeln!(backup_log, "\n{}", msg.clone())?;
+    info!("backup", msg.clone());
+    ui.send(msg).unwrap();
+
+    if resumed.is_some() {
+        let msg = format!("Stored before the interruption: {} ({} files)", report.resumed_size.human_readable(), report.resumed);
+        writeln!(backup_log, "\n{}", msg.clone())?;
+        info!("backup", msg.clone());
+        ui.send(msg).unwrap();
+    }
+
+    let failed_size: u64 = report.failed.iter().map(|failed| failed.size).sum();
+    let msg = format!("Failed: {} ({} files)", failed_size.human_readable(), report.failed.len());
+    writeln!(backup_log, "\n{}", msg.clone())?;
     info!("backup", msg.clone());
     ui.send(msg).unwrap();
 
     let msg = format!("Time elapsed: {}", duration.human_readable());
-    writeln!(backup_log, "\n{}", msg.clone()).unwrap();
+    writeln!(backup_log, "\n{}", msg.clone())?;
     info!("backup", msg.clone());
     ui.send(msg).unwrap();
 
-    Ok(())
-}
\ No newline at end of file
+    if verify {
+        let msg = format!("Verification time elapsed: {}", verify_duration.human_readable());
+        writeln!(backup_log, "\n{}", msg.clone())?;
+        info!("backup", msg.clone());
+        ui.send(msg).unwrap();
+    }
+
+    if report.cancelled {
+        let msg = format!("Snapshot incomplete, the backup was cancelled after {} of {} files.", manifest.entries.len(), files.len());
+        writeln!(backup_log, "\n{}", msg.clone())?;
+        info!("backup", msg.clone());
+        ui.send(msg).unwrap();
+    }
+
+    writeln!(backup_log, "\nResources used:")?;
+    for line in meter.report() {
+        writeln!(backup_log, "{}", line)?;
+    }
+
+    // everything is on the drive before the snapshot is renamed into place, a cancelled one included since it's consistent
+    backup_log.sync_all()?;
+    drop(backup_log);
+    drop(journal);
+    manifest::commit(&backup_root, &timestamp)?;
+
+    Ok(report)
+}
+
+/// splits an absolute path into (root folder in the snapshot, original root, path relative to the root)
+/// the root folder identifies the drive the file comes from: `C:\data\x.txt` is stored as `C\data\x.txt`,
+/// network shares go under `UNC\<server>\<share>` and unix paths under `root`
+fn snapshot_layout(path: &Path) -> (PathBuf, PathBuf, PathBuf) {
+    let mut layout_root = PathBuf::new();
+    let mut original_root = PathBuf::new();
+    let mut relative_path = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => {
+                original_root.push(prefix.as_os_str());
+                match prefix.kind() {
+                    Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => {
+                        layout_root.push((letter as char).to_string());
+                    },
+                    Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+                        layout_root.push("UNC");
+                        layout_root.push(server);
+                        layout_root.push(share);
+                    },
+                    Prefix::DeviceNS(device) => {
+                        layout_root.push("Device");
+                        layout_root.push(device);
+                    },
+                    Prefix::Verbatim(name) => {
+                        layout_root.push("Verbatim");
+                        layout_root.push(name);
+                    }
+                }
+            },
+            Component::RootDir => {
+                original_root.push(component);
+                if layout_root.as_os_str().is_empty() {
+                    layout_root.push("root");
+                }
+            },
+            Component::CurDir => {},
+            Component::ParentDir => { relative_path.pop(); },
+            Component::Normal(name) => { relative_path.push(name); }
+        }
+    }
+    (layout_root, original_root, relative_path)
+}
+
+/// reuses the content of the previous snapshot for an unchanged file:
+/// hard links it when the destination filesystem supports it, otherwise records a reference to it
+fn reuse_previous(entry: &ManifestEntry, previous_snapshot: &str, backup_root: &Path, dest_path: &Path) -> Outcome {
+    let holder = entry.holder(previous_snapshot);
+    match hard_link(backup_root.join(holder).join(&entry.path), dest_path) {
+        Ok(_) => Outcome::Linked,
+        Err(_) => Outcome::Referenced { snapshot: holder.to_string() }
+    }
+}
+
+/// copies the content of a file computing its md5 at the same time, returns the hash
+pub fn copy_hashed(from: &Path, to: &Path) -> io::Result<String> {
+    let mut reader = File::open(from)?;
+    let mut writer = File::create(to)?;
+    let mut hash = md5::default();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hash.update(&buffer[..read]);
+        writer.write_all(&buffer[..read])?;
+    }
+    writer.sync_all()?; // the content must be on the device before it can be verified
+    Ok(hash.digest().to_hex_lowercase())
+}
+
+/// md5 of the content of a file
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut reader = File::open(path)?;
+    let mut hash = md5::default();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hash.update(&buffer[..read]);
+    }
+    Ok(hash.digest().to_hex_lowercase())
+}
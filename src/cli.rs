@@ -0,0 +1,257 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::thread;
+
+use clap::{Parser, Subcommand};
+
+use crate::{backup, config, logger, tokio, TOKIO};
+use crate::backup::{backup_main, BackupOutcome, HumanReadable};
+use crate::control;
+use crate::control::Request;
+use crate::config::DEFAULT_PROFILE;
+use crate::logger::error;
+use crate::state::ApplicationState;
+use crate::restore::{list_main, ListArgs, restore_main, RestoreArgs};
+use crate::retention::{prune_main, PruneArgs};
+use crate::verify::{verify_main, VerifyArgs};
+
+/// Perform a preconfigured backup with a mouse gesture.
+/// Without a command the gesture listener is started, as with `daemon`.
+///
+/// Exit codes: 0 success, 1 failure, 2 usage error, 3 partial backup, 4 configuration error.
+#[derive(Parser)]
+#[command(name = "blackout", version)]
+pub struct Cli {
+    /// Configuration file to use instead of the default blackout.toml
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the tray and listen for the gestures and hotkeys (the default)
+    Daemon,
+    /// Run a backup right away, as if the gesture was completed
+    BackupNow {
+        /// Profile to back up
+        #[arg(long, default_value = DEFAULT_PROFILE)]
+        profile: String,
+    },
+    /// List the snapshots on the mounted drives
+    List(ListArgs),
+    /// Check the files of a snapshot against the hashes in its manifest
+    Verify(VerifyArgs),
+    /// Restore files from a snapshot, the snapshots are listed if none is given
+    Restore(RestoreArgs),
+    /// Delete the snapshots that the retention of their profile doesn't keep
+    Prune(PruneArgs),
+    /// Configuration file commands
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Show the phase of the running instance, the progress of its backup and how the last one ended
+    Status,
+    /// Make the running instance ignore the gestures and hotkeys
+    Pause,
+    /// Make the running instance listen to the gestures and hotkeys again
+    Resume,
+    /// Make the running instance load its configuration file again
+    Reload,
+    /// Cancel the backup of the running instance, it stops after the current file
+    Cancel,
+    /// Quit the running instance, a running backup is cancelled first
+    Quit,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration file and print what it sets up
+    Check,
+}
+
+/// like the clap usage errors
+const EXIT_USAGE: u8 = 2;
+const EXIT_PARTIAL: u8 = 3;
+const EXIT_CONFIG: u8 = 4;
+
+/// console applications are attached to the console of the parent process,
+/// needed in release since the windows subsystem does not open one
+pub fn attach_console() {
+    #[cfg(windows)]
+    unsafe {
+        use winapi::um::wincon::{ATTACH_PARENT_PROCESS, AttachConsole};
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+/// runs a command to completion, they don't need the single instance lock so they can run alongside the listener
+/// (the daemon command is handled by the main)
+pub fn run(command: Command, config_override: Option<PathBuf>) -> ExitCode {
+    logger::spawn!("blackout");
+
+    // the running instance handles the commands that act on it
+    if let Some(code) = forward(&command, config_override.is_some()) {
+        logger::flush!();
+        return code;
+    }
+
+    let path = match config::init(config_override.as_deref()) {
+        Ok(path) => path,
+        Err(e) => {
+            error!("cli", e.clone());
+            eprintln!("{}", e);
+            logger::flush!();
+            return ExitCode::from(EXIT_CONFIG);
+        }
+    };
+
+    let result = match command {
+        Command::Daemon => Ok(ExitCode::SUCCESS),
+        Command::BackupNow { profile } => backup_now(profile),
+        Command::List(args) => list_main(args).map(|_| ExitCode::SUCCESS),
+        Command::Verify(args) => verify_main(args).map(|_| ExitCode::SUCCESS),
+        Command::Restore(args) => restore_main(args).map(|_| ExitCode::SUCCESS),
+        Command::Prune(args) => prune_main(args).map(|_| ExitCode::SUCCESS),
+        Command::Config { command: ConfigCommand::Check } => {
+            config_check(path);
+            Ok(ExitCode::SUCCESS)
+        },
+        Command::Status | Command::Pause | Command::Resume | Command::Reload | Command::Cancel | Command::Quit => Ok(ExitCode::SUCCESS) // forwarded
+    };
+
+    let code = match result {
+        Ok(code) => code,
+        Err(e) => {
+            error!("cli", format!("{}", e));
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    };
+
+    logger::flush!();
+
+    code
+}
+
+/// sends the command to the running instance, none if it has to run here
+/// a backup runs here when there is no running instance, the other commands need one
+/// the running instance has its own configuration, so another one can't be given to the commands it handles
+fn forward(command: &Command, config_override: bool) -> Option<ExitCode> {
+    let request = match command {
+        Command::BackupNow { profile } => Request::Trigger { profile: profile.clone(), wait: true },
+        Command::Status => Request::Status,
+        Command::Pause => Request::Pause,
+        Command::Resume => Request::Resume,
+        Command::Reload => Request::Reload,
+        Command::Cancel => Request::Cancel,
+        Command::Quit => Request::Quit,
+        _ => { return None; }
+    };
+    if config_override && (!matches!(request, Request::Trigger { .. }) || control::send(&Request::Status).is_ok()) {
+        let msg = "--config can't be used with a command handled by the running instance, it uses its own configuration.";
+        error!("cli", msg);
+        eprintln!("{}", msg);
+        return Some(ExitCode::from(EXIT_USAGE));
+    }
+    let response = match control::send(&request) {
+        Ok(response) => response,
+        Err(_) if matches!(request, Request::Trigger { .. }) => { return None; },
+        Err(e) => {
+            error!("cli", format!("Cannot reach the running instance: {}", e));
+            eprintln!("Blackout is not running.");
+            return Some(ExitCode::FAILURE);
+        }
+    };
+
+    if !response.ok {
+        eprintln!("{}", response.message);
+        let code = if matches!(request, Request::Reload) { EXIT_CONFIG } else { 1 };
+        return Some(ExitCode::from(code));
+    }
+    println!("{}", response.message);
+    if let Some(status) = response.status {
+        if let ApplicationState::BackingUp(progress) | ApplicationState::Verifying(progress) = status.state {
+            println!("{} / {} files, {} / {}", progress.files, progress.total_files,
+                     progress.size.human_readable(), progress.total_size.human_readable());
+            if let Some(current) = progress.current {
+                println!("Current file: {}", current.display());
+            }
+        }
+        if let Some(result) = status.last_result {
+            println!("Last backup: {} ({}) {}", result.profile, result.finished, result.message);
+        }
+    }
+    Some(response.outcome.map(ExitCode::from).unwrap_or(ExitCode::SUCCESS))
+}
+
+/// the backup runs with its console and sounds as if it was triggered by the gesture
+fn backup_now(profile: String) -> Result<ExitCode, Box<dyn Error>> {
+    if !config::get().has_profile(&profile) {
+        return Err(format!("Unknown profile: {}", profile).into());
+    }
+    // ctrl+c cancels the backup, which stops after the current file
+    tokio!().spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            backup::cancel();
+        }
+    });
+    // off the tokio thread, like when it's triggered by the gesture
+    let outcome = thread::spawn(move || backup_main(profile)).join().unwrap_or(BackupOutcome::Failure);
+    Ok(outcome.into())
+}
+
+/// the configuration is valid at this point, prints what it sets up
+fn config_check(path: Option<PathBuf>) {
+    let config = config::get();
+    match path {
+        Some(path) => println!("Configuration {} is valid.", path.display()),
+        None => println!("No configuration file found, using defaults and sources.txt.")
+    }
+    for profile in config.profile_names() {
+        let sources = config.sources(&profile);
+        let mut source_list: Vec<String> = sources.paths.iter().cloned()
+            .chain(sources.entries.iter().map(|entry| entry.path.clone()))
+            .collect();
+        if let Some(file) = &sources.file {
+            source_list.push(format!("paths in {}", file.display()));
+        }
+        println!("\nProfile {}:", profile);
+        println!("  sources:     {}", source_list.join(", "));
+        println!("  destination: {}", config.destination(&profile).folder);
+        println!("  retention:   {}", config.destination(&profile).retention.describe());
+        if let Some((gesture, _)) = config.gestures().into_iter().find(|(_, bound)| *bound == profile) {
+            println!("  gesture:     {}", gesture);
+        }
+        if let Some((hotkey, _)) = config.hotkeys().into_iter().find(|(_, bound)| *bound == profile) {
+            println!("  hotkey:      {}", hotkey.join(", "));
+        }
+    }
+    println!();
+    if let Some(confirm) = &config.gesture.confirm {
+        println!("Confirm gesture: {}", confirm);
+    }
+    if let Some(abort) = &config.gesture.abort {
+        println!("Abort gesture:   {}", abort);
+    }
+    if !config.hotkey.confirm.is_empty() {
+        println!("Confirm hotkey:  {}", config.hotkey.confirm.join(", "));
+    }
+    if !config.hotkey.abort.is_empty() {
+        println!("Abort hotkey:    {}", config.hotkey.abort.join(", "));
+    }
+}
+
+/// 0 on success, 3 on partial success, 1 on failure
+impl From<BackupOutcome> for ExitCode {
+    fn from(outcome: BackupOutcome) -> Self {
+        match outcome {
+            BackupOutcome::Success => ExitCode::SUCCESS,
+            BackupOutcome::PartialSuccess => ExitCode::from(EXIT_PARTIAL),
+            BackupOutcome::Failure => ExitCode::FAILURE
+        }
+    }
+}
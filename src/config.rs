@@ -0,0 +1,527 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use glob::PatternError;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::backup::HumanReadable;
+use crate::filters::Filters;
+use crate::gesture::{BUILTIN_GESTURES, GestureDefinition};
+use crate::input::InputBackend;
+use crate::keyboard::parse_chord;
+use crate::screen::Monitor;
+
+/// name of the configuration file
+pub const CONFIG_FILE: &str = "blackout.toml";
+/// sources file of the previous versions, used when there is no configuration file
+const LEGACY_SOURCES_FILE: &str = "sources.txt";
+/// profile made of the top level sources and destination
+pub const DEFAULT_PROFILE: &str = "default";
+
+lazy_static! {
+    static ref CONFIG : RwLock<Arc<Config>> = RwLock::new(Arc::new(Config::legacy()));
+    /// the configuration file given on the command line, kept for reloading
+    static ref OVERRIDE : RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub sources: SourcesConfig,
+    pub destination: DestinationConfig,
+    pub gesture: GestureConfig,
+    pub hotkey: HotkeyConfig,
+    pub audio: AudioConfig,
+    pub logging: LoggingConfig,
+    pub ui: UiConfig,
+    /// named backup profiles, in addition to the default one
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    /// gesture definitions, in addition to the built-in ones
+    pub gestures: BTreeMap<String, GestureDefinition>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// replaces the top level sources
+    pub sources: Option<SourcesConfig>,
+    /// replaces the top level destination
+    pub destination: Option<DestinationConfig>,
+    /// name of the mouse gesture that triggers this profile
+    pub gesture: Option<String>,
+    /// key sequence that arms this profile
+    pub hotkey: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourcesConfig {
+    /// glob paths of the files and folders to back up
+    pub paths: Vec<String>,
+    /// file with additional glob paths, one per line, relative to the configuration file
+    pub file: Option<PathBuf>,
+    /// sources with their own filters
+    pub entries: Vec<SourceEntry>,
+    /// gitignore style rules applied to all the sources
+    pub exclude: Vec<String>,
+    /// filters applied to all the sources, unless a source sets its own
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub max_age_days: Option<u64>,
+    pub extensions: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceEntry {
+    /// glob path of the files or folder to back up
+    pub path: String,
+    /// gitignore style rules, relative to the folder of the source
+    pub exclude: Vec<String>,
+    /// size limits in bytes
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// files modified more than this many days ago are left out
+    pub max_age_days: Option<u64>,
+    /// only files with these extensions are backed up
+    pub extensions: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DestinationConfig {
+    /// folder created in the destination drive to hold the snapshots
+    pub folder: String,
+    /// read back the copied files and check them against their hash
+    pub verify: bool,
+    /// only copy the files that changed since the latest snapshot on the drive
+    pub incremental: bool,
+    /// which snapshots are kept on the drive, all of them if nothing is set
+    pub retention: RetentionConfig,
+}
+
+/// a snapshot is kept if any of the counts keeps it, then the oldest ones go until the size fits
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// the most recent snapshots
+    pub keep_last: Option<usize>,
+    /// the most recent snapshot of each of the last days, weeks and months that have one
+    pub keep_daily: Option<usize>,
+    pub keep_weekly: Option<usize>,
+    pub keep_monthly: Option<usize>,
+    /// bytes of content held by the snapshots of the profile, each content counted once
+    pub max_total_size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GestureConfig {
+    /// name of the gesture that arms the default profile
+    pub path: String,
+    /// gesture that confirms an armed backup, the arming gesture again if missing
+    pub confirm: Option<String>,
+    /// gesture that cancels the running backup
+    pub abort: Option<String>,
+    /// an armed backup is disarmed if not confirmed within this time
+    pub arm_timeout_secs: u64,
+    /// monitor the gestures are drawn on: "primary", "virtual" (all of them) or the index of a display
+    pub monitor: Monitor,
+    /// distance from the screen borders within which the path must be drawn, as a fraction of the screen width
+    pub margin: f64,
+    /// os input events or polling, polling is the fallback when the events are not available
+    pub input: InputBackend,
+    /// interval between two mouse position probes of the polling input
+    pub probe_interval_ms: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HotkeyConfig {
+    /// key sequence that arms the default profile, e.g. ["Ctrl+Alt+B"], no hotkey if empty
+    pub arm: Vec<String>,
+    /// key sequence that confirms an armed backup, the arming one again if empty
+    pub confirm: Vec<String>,
+    /// key sequence that cancels the running backup
+    pub abort: Vec<String>,
+    /// time allowed between two chords of a sequence
+    pub step_timeout_ms: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// pause between two heartbeats while the backup is running
+    pub heartbeat_interval_ms: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// interval between two cpu usage logs
+    pub cpu_interval_secs: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiConfig {
+    /// how long the backup console stays open after the backup ends
+    pub linger_secs: u64,
+}
+
+impl Default for DestinationConfig {
+    fn default() -> Self {
+        Self {
+            folder: "BlackoutBackup".to_string(),
+            verify: true,
+            incremental: true,
+            retention: RetentionConfig::default(),
+        }
+    }
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            path: BUILTIN_GESTURES[0].to_string(),
+            confirm: None,
+            abort: None,
+            arm_timeout_secs: 10,
+            monitor: Monitor::default(),
+            margin: 1.0 / 6.0,
+            input: InputBackend::Events,
+            probe_interval_ms: 200,
+        }
+    }
+}
+
+impl Default for HotkeyConfig {
+    fn default() -> Self {
+        Self {
+            arm: Vec::new(),
+            confirm: Vec::new(),
+            abort: Vec::new(),
+            step_timeout_ms: 1500,
+        }
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval_ms: 1000,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            cpu_interval_secs: 120,
+        }
+    }
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            linger_secs: 5,
+        }
+    }
+}
+
+impl SourceEntry {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+impl SourcesConfig {
+    /// filters of a source: the global exclude rules followed by its own, its own filters replace the global ones
+    pub fn filters(&self, entry: &SourceEntry) -> Result<Filters, PatternError> {
+        let exclude: Vec<String> = self.exclude.iter().chain(entry.exclude.iter()).cloned().collect();
+        Filters::new(
+            &exclude,
+            entry.min_size.or(self.min_size),
+            entry.max_size.or(self.max_size),
+            entry.max_age_days.or(self.max_age_days),
+            entry.extensions.as_ref().or(self.extensions.as_ref())
+        )
+    }
+}
+
+impl Config {
+    /// configuration used when there is no configuration file: defaults with the sources from `sources.txt`
+    fn legacy() -> Self {
+        let mut config = Config::default();
+        config.sources.file = Some(PathBuf::from(LEGACY_SOURCES_FILE));
+        config
+    }
+
+    /// reads and validates a configuration file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let mut config: Config = toml::from_str(&text).map_err(|e| format!("Invalid configuration in {}: {}", path.display(), e))?;
+        // the working directory is arbitrary when started at logon, so the sources files are next to the configuration
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let sources = [&mut config.sources].into_iter().chain(config.profiles.values_mut().filter_map(|profile| profile.sources.as_mut()));
+        for file in sources.filter_map(|sources| sources.file.as_mut()).filter(|file| file.is_relative()) {
+            *file = dir.join(&file);
+        }
+        config.validate().map_err(|e| format!("Invalid configuration in {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.sources.validate("sources")?;
+        self.destination.validate("destination")?;
+        for (name, gesture) in self.gestures.iter() {
+            if BUILTIN_GESTURES.contains(&name.as_str()) {
+                return Err(format!("[gestures.{}] is a built-in gesture", name));
+            }
+            gesture.validate().map_err(|e| format!("[gestures.{}] {}", name, e))?;
+        }
+        if self.gesture_definition(&self.gesture.path).is_none() {
+            return Err(format!("[gesture] unknown gesture '{}'", self.gesture.path));
+        }
+        for (key, gesture) in [("confirm", &self.gesture.confirm), ("abort", &self.gesture.abort)] {
+            if let Some(gesture) = gesture {
+                if self.gesture_definition(gesture).is_none() {
+                    return Err(format!("[gesture] unknown {} gesture '{}'", key, gesture));
+                }
+            }
+        }
+        self.gesture.monitor.validate().map_err(|e| format!("[gesture] {}", e))?;
+        if self.gesture.arm_timeout_secs == 0 {
+            return Err("[gesture] arm_timeout_secs must be greater than 0".to_string());
+        }
+        let mut gestures = vec![&self.gesture.path];
+        for (name, profile) in self.profiles.iter() {
+            if name == DEFAULT_PROFILE || name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return Err(format!("[profiles.{}] profile names must be made of letters, digits, '-' and '_', and can't be '{}'", name, DEFAULT_PROFILE));
+            }
+            if let Some(sources) = &profile.sources {
+                sources.validate(&format!("profiles.{}.sources", name))?;
+            }
+            if let Some(destination) = &profile.destination {
+                destination.validate(&format!("profiles.{}.destination", name))?;
+            }
+            if let Some(gesture) = &profile.gesture {
+                if self.gesture_definition(gesture).is_none() {
+                    return Err(format!("[profiles.{}] unknown gesture '{}'", name, gesture));
+                }
+                if gestures.contains(&gesture) {
+                    return Err(format!("[profiles.{}] gesture '{}' is already bound to another profile", name, gesture));
+                }
+                gestures.push(gesture);
+            }
+        }
+        // the same motion would arm and confirm, missing is fine since it means drawing the arming gesture again
+        if let Some(confirm) = &self.gesture.confirm {
+            if gestures.contains(&confirm) {
+                return Err(format!("[gesture] confirm gesture '{}' is already an arming gesture", confirm));
+            }
+        }
+        if !(self.gesture.margin > 0.0 && self.gesture.margin < 0.5) {
+            return Err(format!("[gesture] margin must be between 0 and 0.5, found {}", self.gesture.margin));
+        }
+        if self.gesture.probe_interval_ms == 0 {
+            return Err("[gesture] probe_interval_ms must be greater than 0".to_string());
+        }
+        self.validate_hotkeys()?;
+        if self.audio.heartbeat_interval_ms == 0 {
+            return Err("[audio] heartbeat_interval_ms must be greater than 0".to_string());
+        }
+        if self.logging.cpu_interval_secs == 0 {
+            return Err("[logging] cpu_interval_secs must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    /// names of all the profiles, the default one first
+    pub fn profile_names(&self) -> Vec<String> {
+        [DEFAULT_PROFILE.to_string()].into_iter().chain(self.profiles.keys().cloned()).collect()
+    }
+
+    pub fn has_profile(&self, profile: &str) -> bool {
+        profile == DEFAULT_PROFILE || self.profiles.contains_key(profile)
+    }
+
+    /// sources of a profile, the top level ones if the profile does not define its own
+    pub fn sources(&self, profile: &str) -> &SourcesConfig {
+        self.profiles.get(profile).and_then(|profile| profile.sources.as_ref()).unwrap_or(&self.sources)
+    }
+
+    /// destination of a profile, the top level one if the profile does not define its own
+    pub fn destination(&self, profile: &str) -> &DestinationConfig {
+        self.profiles.get(profile).and_then(|profile| profile.destination.as_ref()).unwrap_or(&self.destination)
+    }
+
+    /// (gesture, profile) bindings
+    pub fn gestures(&self) -> Vec<(String, String)> {
+        [(self.gesture.path.clone(), DEFAULT_PROFILE.to_string())].into_iter()
+            .chain(self.profiles.iter().filter_map(|(name, profile)| profile.gesture.clone().map(|gesture| (gesture, name.clone()))))
+            .collect()
+    }
+
+    /// (key sequence, profile) bindings
+    pub fn hotkeys(&self) -> Vec<(Vec<String>, String)> {
+        [(self.hotkey.arm.clone(), DEFAULT_PROFILE.to_string())].into_iter()
+            .chain(self.profiles.iter().filter_map(|(name, profile)| profile.hotkey.clone().map(|hotkey| (hotkey, name.clone()))))
+            .filter(|(hotkey, _)| !hotkey.is_empty())
+            .collect()
+    }
+
+    fn validate_hotkeys(&self) -> Result<(), String> {
+        let sequences = [("confirm", &self.hotkey.confirm), ("abort", &self.hotkey.abort)].into_iter()
+            .map(|(key, sequence)| (format!("[hotkey] {}", key), sequence.clone()))
+            .chain(self.hotkeys().into_iter().map(|(sequence, profile)| (format!("hotkey of profile {}", profile), sequence)));
+        let mut bound = Vec::new();
+        for (name, sequence) in sequences {
+            for chord in sequence.iter() {
+                parse_chord(chord).map_err(|e| format!("{}: {}", name, e))?;
+            }
+            if !sequence.is_empty() && !name.starts_with("[hotkey]") {
+                if bound.contains(&sequence) {
+                    return Err(format!("{}: the key sequence is already bound to another profile", name));
+                }
+                bound.push(sequence);
+            }
+        }
+        if !self.hotkey.confirm.is_empty() && bound.contains(&self.hotkey.confirm) {
+            return Err("[hotkey] confirm: the key sequence is already an arming one".to_string());
+        }
+        if self.hotkey.step_timeout_ms == 0 {
+            return Err("[hotkey] step_timeout_ms must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    /// a gesture defined in the configuration or a built-in one
+    pub fn gesture_definition(&self, name: &str) -> Option<GestureDefinition> {
+        self.gestures.get(name).cloned().or_else(|| GestureDefinition::builtin(name))
+    }
+
+    /// snapshot folders of all the profiles
+    pub fn destination_folders(&self) -> Vec<String> {
+        let mut folders: Vec<String> = Vec::new();
+        for profile in self.profile_names() {
+            let folder = &self.destination(&profile).folder;
+            if !folders.contains(folder) {
+                folders.push(folder.clone());
+            }
+        }
+        folders
+    }
+}
+
+impl SourcesConfig {
+    fn validate(&self, section: &str) -> Result<(), String> {
+        if self.paths.is_empty() && self.file.is_none() && self.entries.is_empty() {
+            return Err(format!("[{}] no paths, file nor entries specified", section));
+        }
+        for entry in self.entries.iter() {
+            if entry.path.trim().is_empty() {
+                return Err(format!("[[{}.entries]] path is missing", section));
+            }
+        }
+        for entry in self.entries.iter().chain([SourceEntry::default()].iter()) {
+            self.filters(entry).map_err(|e| format!("[{}] invalid exclude rule: {}", section, e))?;
+            if let (Some(min_size), Some(max_size)) = (entry.min_size.or(self.min_size), entry.max_size.or(self.max_size)) {
+                if min_size > max_size {
+                    return Err(format!("[{}] min_size {} is greater than max_size {}", section, min_size, max_size));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DestinationConfig {
+    fn validate(&self, section: &str) -> Result<(), String> {
+        if self.folder.is_empty() || self.folder.contains(['/', '\\']) {
+            return Err(format!("[{}] folder must be a plain folder name, found '{}'", section, self.folder));
+        }
+        let retention = &self.retention;
+        for (key, count) in [("keep_last", retention.keep_last), ("keep_daily", retention.keep_daily), ("keep_weekly", retention.keep_weekly), ("keep_monthly", retention.keep_monthly)] {
+            if count == Some(0) {
+                return Err(format!("[{}.retention] {} must be greater than 0", section, key));
+            }
+        }
+        if retention.max_total_size == Some(0) {
+            return Err(format!("[{}.retention] max_total_size must be greater than 0", section));
+        }
+        Ok(())
+    }
+}
+
+impl RetentionConfig {
+    /// whether any snapshot can be deleted
+    pub fn is_set(&self) -> bool {
+        self.counted() || self.max_total_size.is_some()
+    }
+
+    /// whether the snapshots are kept by the counts, otherwise they are all kept until the size limit
+    pub fn counted(&self) -> bool {
+        self.keep_last.is_some() || self.keep_daily.is_some() || self.keep_weekly.is_some() || self.keep_monthly.is_some()
+    }
+
+    /// e.g. "last 3, daily 7, at most 10.00 GB"
+    pub fn describe(&self) -> String {
+        let mut rules: Vec<String> = [("last", self.keep_last), ("daily", self.keep_daily), ("weekly", self.keep_weekly), ("monthly", self.keep_monthly)].into_iter()
+            .filter_map(|(name, count)| count.map(|count| format!("{} {}", name, count)))
+            .collect();
+        if let Some(size) = self.max_total_size {
+            rules.push(format!("at most {}", size.human_readable()));
+        }
+        if rules.is_empty() { "keep all".to_string() } else { rules.join(", ") }
+    }
+}
+
+/// path of the configuration file: the override if given, otherwise `blackout/blackout.toml` in the user configuration
+/// directory (XDG_CONFIG_HOME on linux, AppData on windows), falling back to `blackout.toml` in the working directory
+pub fn locate(config_override: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = config_override {
+        return Some(path.to_path_buf());
+    }
+    dirs::config_dir()
+        .map(|dir| dir.join("blackout").join(CONFIG_FILE))
+        .into_iter()
+        .chain([PathBuf::from(CONFIG_FILE)])
+        .find(|path| path.is_file())
+}
+
+/// loads the configuration into the global one, without a configuration file the legacy one is used
+/// returns the path of the loaded file
+pub fn init(config_override: Option<&Path>) -> Result<Option<PathBuf>, String> {
+    let path = locate(config_override);
+    let config = match &path {
+        Some(path) => Config::load(path)?,
+        None => Config::legacy()
+    };
+    *CONFIG.write().unwrap() = Arc::new(config);
+    *OVERRIDE.write().unwrap() = config_override.map(Path::to_path_buf);
+    Ok(path)
+}
+
+/// path of the configuration file in use, none if there is none
+pub fn path() -> Option<PathBuf> {
+    locate(OVERRIDE.read().unwrap().as_deref())
+}
+
+/// loads the configuration again from the same place, the current one is kept if the new one is invalid
+pub fn reload() -> Result<Option<PathBuf>, String> {
+    let config_override = OVERRIDE.read().unwrap().clone();
+    init(config_override.as_deref())
+}
+
+/// current configuration
+pub fn get() -> Arc<Config> {
+    CONFIG.read().unwrap().clone()
+}
@@ -0,0 +1,208 @@
+use std::error::Error;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::thread;
+
+use interprocess::local_socket::{GenericNamespaced, ListenerOptions, Name, Stream};
+use interprocess::local_socket::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{backup, config, trigger};
+use crate::backup::{backup_main, BackupOutcome};
+use crate::logger::{error, info};
+use crate::state::{APP_STATE, ApplicationState, BackupResult};
+use crate::trigger::Control;
+
+/// a request to the running instance, sent as a json line
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// back up a profile, waiting for the outcome if asked
+    Trigger { profile: String, wait: bool },
+    Status,
+    /// stop and start listening to the gestures and hotkeys
+    Pause,
+    Resume,
+    /// load the configuration file again
+    Reload,
+    /// stop the running backup after its current file
+    Cancel,
+    Quit,
+}
+
+/// the answer of the running instance, sent as a json line
+#[derive(Serialize, Deserialize, Default)]
+pub struct Response {
+    pub ok: bool,
+    pub message: String,
+    #[serde(default)]
+    pub status: Option<Status>,
+    /// the outcome of a backup that was waited for
+    #[serde(default)]
+    pub outcome: Option<BackupOutcome>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Status {
+    /// the phase with its payload
+    pub state: ApplicationState,
+    pub last_result: Option<BackupResult>,
+}
+
+impl Response {
+    fn ok(message: impl Into<String>) -> Self {
+        Self { ok: true, message: message.into(), ..Default::default() }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Self { ok: false, message: message.into(), ..Default::default() }
+    }
+}
+
+/// name of the control endpoint, a named pipe on windows and an abstract unix socket on linux, one per user
+/// the named pipes only let their owner write, the abstract sockets have no permissions so `serve` checks the peer
+fn endpoint() -> io::Result<Name<'static>> {
+    #[cfg(not(windows))]
+    let user = current_uid().to_string();
+    #[cfg(windows)]
+    let user = std::env::var("USERNAME").unwrap_or_default();
+    format!("blackout-{}.sock", user).to_ns_name::<GenericNamespaced>().map(Name::into_owned)
+}
+
+#[cfg(not(windows))]
+fn current_uid() -> libc::uid_t {
+    unsafe { libc::getuid() }
+}
+
+/// whether the connection comes from the user running blackout
+#[cfg(not(windows))]
+fn same_user(stream: &Stream) -> bool {
+    stream.peer_creds().ok().and_then(|creds| creds.euid()) == Some(current_uid())
+}
+
+#[cfg(windows)]
+fn same_user(_stream: &Stream) -> bool {
+    true
+}
+
+/// sends a request to the running instance, fails if there is none
+pub fn send(request: &Request) -> Result<Response, Box<dyn Error>> {
+    let stream = Stream::connect(endpoint()?)?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    (&stream).write_all(line.as_bytes())?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// listens for the requests of the other blackout invocations, each connection carries one request
+/// the thread is not joined, it's blocked on the endpoint until the process exits
+pub fn control_main() {
+    let listener = match endpoint().and_then(|name| ListenerOptions::new().name(name).create_sync()) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("control", format!("Cannot listen on the control endpoint! {}", e));
+            return;
+        }
+    };
+    info!("control", "Listening on the control endpoint.");
+
+    for stream in listener.incoming() {
+        if APP_STATE.read() == ApplicationState::Quit {
+            break;
+        }
+        match stream {
+            // a waited backup keeps its connection open, the others are answered right away
+            Ok(stream) => { thread::spawn(move || serve(stream)); },
+            Err(e) => { error!("control", format!("Control connection failed! {}", e)); }
+        }
+    }
+}
+
+fn serve(stream: Stream) {
+    if !same_user(&stream) {
+        error!("control", "Control connection refused, it comes from another user!");
+        return;
+    }
+    let mut line = String::new();
+    if let Err(e) = BufReader::new(&stream).read_line(&mut line) {
+        error!("control", format!("Cannot read the control request! {}", e));
+        return;
+    }
+    let (response, quit) = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => {
+            let quit = matches!(request, Request::Quit);
+            (handle(request), quit)
+        },
+        Err(e) => (Response::error(format!("Invalid request: {}", e)), false)
+    };
+    let mut line = serde_json::to_string(&response).unwrap_or_default();
+    line.push('\n');
+    (&stream).write_all(line.as_bytes()).ok();
+    // the answer goes out before the process starts quitting
+    if quit {
+        APP_STATE.change(ApplicationState::Quit).ok();
+    }
+}
+
+fn handle(request: Request) -> Response {
+    match request {
+        Request::Trigger { profile, wait } => {
+            if !config::get().has_profile(&profile) {
+                return Response::error(format!("Unknown profile: {}", profile));
+            }
+            if backup::is_running() {
+                return Response::error("Another backup is running.");
+            }
+            info!("control", format!("Backup of profile {} started from the control endpoint.", profile));
+            let backup = thread::spawn(move || backup_main(profile));
+            if !wait {
+                return Response::ok("Backup started.");
+            }
+            let outcome = backup.join().unwrap_or(BackupOutcome::Failure);
+            Response { outcome: Some(outcome), ..Response::ok("Backup finished.") }
+        },
+        Request::Status => {
+            let state = APP_STATE.read();
+            let status = Status { state: state.clone(), last_result: APP_STATE.last_result() };
+            Response { status: Some(status), ..Response::ok(format!("Blackout is {}.", state)) }
+        },
+        Request::Pause => match APP_STATE.change(ApplicationState::Paused) {
+            Ok(_) => Response::ok("Triggers paused."),
+            Err(e) => Response::error(format!("Cannot pause, {}.", e))
+        },
+        Request::Resume => {
+            // idle can be reached from other phases too
+            if APP_STATE.read() != ApplicationState::Paused {
+                return Response::error("Blackout is not paused.");
+            }
+            match APP_STATE.change(ApplicationState::Idle) {
+                Ok(_) => Response::ok("Triggers resumed."),
+                Err(e) => Response::error(format!("Cannot resume, {}.", e))
+            }
+        },
+        Request::Reload => match config::reload() {
+            Ok(_) => {
+                info!("control", "Configuration reloaded.");
+                trigger::control(Control::Reload);
+                Response::ok("Configuration reloaded.")
+            },
+            Err(e) => {
+                error!("control", format!("Configuration not reloaded! {}", e));
+                Response::error(e)
+            }
+        },
+        Request::Cancel => {
+            if !backup::cancel() {
+                return Response::error("No backup is running.");
+            }
+            info!("control", "Backup cancelled from the control endpoint.");
+            Response::ok("Backup cancelled.")
+        },
+        Request::Quit => {
+            info!("control", "Quitted from the control endpoint.");
+            Response::ok("Quitting.")
+        }
+    }
+}
@@ -1,13 +1,18 @@
-#![
+#![cfg_attr(
+    windows,
     windows_subsystem = "windows"
-]
+)]
 
+#[cfg(windows)]
 use std::io;
 use std::io::{BufRead, stdout, Write};
 
+#[cfg(windows)]
 use winapi::um::consoleapi::AllocConsole;
+#[cfg(windows)]
 use winapi::um::wincon::FreeConsole;
 
+#[cfg(windows)]
 fn main() {
     unsafe {
         FreeConsole();
@@ -33,4 +38,67 @@ fn main() {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// runs inside a terminal emulator and follows the status file given as argument,
+/// holding `<status file>.lock` while running and quitting when blackout removes it
+#[cfg(not(windows))]
+fn main() {
+    use std::fs::{File, OpenOptions};
+    use std::io::BufReader;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::Duration;
+
+    use fs2::FileExt;
+
+    let status = match std::env::args_os().nth(1) {
+        Some(status) => PathBuf::from(status),
+        None => {
+            eprintln!("Usage: echo <status file>");
+            return;
+        }
+    };
+    let lock_path = PathBuf::from(format!("{}.lock", status.display()));
+
+    // held until echo quits
+    let _lock = match OpenOptions::new().write(true).create(true).truncate(false).open(&lock_path)
+        .and_then(|lock| lock.try_lock_exclusive().map(|_| lock)) {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("Failed to lock {}: {}", lock_path.display(), e);
+            return;
+        }
+    };
+
+    let mut reader = match File::open(&status) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", status.display(), e);
+            return;
+        }
+    };
+
+    let mut line = String::new();
+    loop {
+        match reader.read_line(&mut line) {
+            Ok(0) => { // caught up with the backup
+                if !lock_path.exists() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(200));
+            }
+            Ok(_) => {
+                if line.ends_with('\n') { // otherwise wait for the rest of the line
+                    print!("{}", line);
+                    stdout().flush().unwrap();
+                    line.clear();
+                }
+            }
+            Err(e) => {
+                eprintln!("Error reading line: {}", e);
+                break;
+            }
+        }
+    }
+}
@@ -1,26 +1,83 @@
 use std::error::Error;
-use std::io::{BufWriter, Write};
+#[cfg(not(windows))]
+use std::fs::{File, remove_file};
+#[cfg(windows)]
+use std::io::BufWriter;
+use std::io::Write;
+#[cfg(not(windows))]
+use std::path::{absolute, PathBuf};
 use std::process::{Command, Stdio};
-use std::sync::mpsc::Receiver;
+#[cfg(not(windows))]
+use std::thread;
+#[cfg(not(windows))]
+use std::time::{Duration, Instant};
 
+use crossbeam::channel::Receiver;
+use crossbeam::select;
+#[cfg(not(windows))]
+use fs2::FileExt;
+
+use crate::backup::HumanReadable;
 use crate::logger::{error, info};
 use crate::state::{APP_STATE, ApplicationState};
-use crate::TOKIO;
-use crate::tokio;
+
+/// status lines written by the backup on linux, followed by echo in a terminal or read as is when headless
+#[cfg(not(windows))]
+pub const STATUS_FILE: &str = "blackout.status";
+
+/// the status file lives in the user runtime directory, or in the temp directory when there is none
+#[cfg(not(windows))]
+pub fn status_path() -> PathBuf {
+    dirs::runtime_dir().unwrap_or_else(std::env::temp_dir).join(STATUS_FILE)
+}
+
+/// terminal emulators tried after $TERMINAL, with the arguments that precede the command to run
+#[cfg(not(windows))]
+const TERMINALS: &[(&str, &[&str])] = &[
+    ("x-terminal-emulator", &["-e"]),
+    ("gnome-terminal", &["--"]),
+    ("konsole", &["-e"]),
+    ("xfce4-terminal", &["-x"]),
+    ("alacritty", &["-e"]),
+    ("kitty", &[]),
+    ("xterm", &["-e"]),
+];
+
+/// how long a terminal has to start echo before the next one is tried
+#[cfg(not(windows))]
+const TERMINAL_TIMEOUT: Duration = Duration::from_secs(3);
 
 pub struct Echo {
     child: Option<std::process::Child>,
+    #[cfg(windows)]
     stdin_writer: Option<BufWriter<std::process::ChildStdin>>,
+    /// the status file, on linux echo follows it instead of reading its stdin since it runs under a terminal
+    #[cfg(not(windows))]
+    status: Option<File>,
+    /// no terminal could be started, the status is only written to the file
+    #[cfg(not(windows))]
+    headless: bool,
+    /// the status file couldn't be created, the backup goes on without showing its status
+    #[cfg(not(windows))]
+    no_status: bool,
 }
 
 impl Echo {
     pub(crate) fn new() -> Self {
         Self {
             child: None,
+            #[cfg(windows)]
             stdin_writer: None,
+            #[cfg(not(windows))]
+            status: None,
+            #[cfg(not(windows))]
+            headless: false,
+            #[cfg(not(windows))]
+            no_status: false,
         }
     }
 
+    #[cfg(windows)]
     pub(crate) fn spawn(&mut self) -> Result<(), Box<dyn Error>> {
         let mut child = Command::new("echo.exe")
             .stdin(Stdio::piped())
@@ -33,6 +90,64 @@ impl Echo {
         Ok(())
     }
 
+    /// opens a terminal running echo on the status file, trying $TERMINAL first and then the common emulators,
+    /// falls back to headless if there is no display or none of them starts
+    #[cfg(not(windows))]
+    pub(crate) fn spawn(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.status.is_none() {
+            match File::create(status_path()) { // a new status for each backup
+                Ok(status) => { self.status = Some(status); },
+                Err(e) => {
+                    error!("echo", format!("Couldn't create the status file {}, the backup continues without it! {:?}", status_path().display(), e));
+                    self.no_status = true;
+                    return Ok(());
+                }
+            }
+        }
+        if self.headless {
+            return Ok(());
+        }
+
+        // echo sits next to blackout, without it there is nothing to start in a terminal
+        let echo = std::env::current_exe().map(|exe| exe.with_file_name("echo"))
+            .inspect_err(|e| { error!("echo", format!("Cannot locate echo! {:?}", e)); })
+            .ok()
+            .filter(|_| std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some());
+        if let Some(echo) = echo {
+            // $TMPDIR can be relative, and echo runs in the working directory of the terminal
+            let status = absolute(status_path()).unwrap_or(status_path());
+            for (terminal, args) in terminals() {
+                let child = Command::new(&terminal)
+                    .args(args)
+                    .arg(&echo)
+                    .arg(&status)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn();
+                let Ok(mut child) = child else { continue; };
+
+                // some terminals hand the window to a server and exit right away, so echo itself is waited for
+                let start = Instant::now();
+                while start.elapsed() < TERMINAL_TIMEOUT {
+                    if echo_running() {
+                        info!("echo", format!("Echo started in {}.", terminal));
+                        self.child = Some(child);
+                        return Ok(());
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+                child.kill().ok();
+                child.wait().ok();
+            }
+        }
+
+        self.headless = true;
+        info!("echo", format!("No terminal available, the backup status is written to {}.", status_path().display()));
+        Ok(())
+    }
+
+    #[cfg(windows)]
     pub(crate) fn write(&mut self, message: String) -> Result<(), Box<dyn Error>> {
         if let Some(writer) = &mut self.stdin_writer {
             writeln!(writer, "{}", message)?;
@@ -41,44 +156,130 @@ impl Echo {
         Ok(())
     }
 
+    #[cfg(not(windows))]
+    pub(crate) fn write(&mut self, message: String) -> Result<(), Box<dyn Error>> {
+        if let Some(status) = &mut self.status {
+            writeln!(status, "{}", message)?;
+            status.flush()?;
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
     pub(crate) fn check_alive(&mut self) -> bool {
         if let Some(child) = self.child.as_mut() {
             child.try_wait().ok().flatten().is_none()
         } else { false }
     }
 
+    /// echo holds the lock of the status file while its terminal is open
+    #[cfg(not(windows))]
+    pub(crate) fn check_alive(&mut self) -> bool {
+        if let Some(child) = self.child.as_mut() {
+            child.try_wait().ok(); // reap the terminals that already exited
+        }
+        self.no_status || (self.status.is_some() && (self.headless || echo_running()))
+    }
+
+    #[cfg(windows)]
     pub fn kill(&mut self) -> Result<(), Box<dyn Error>> {
         if let Some(child) = self.child.as_mut() {
             child.kill()?;
         }
         Ok(())
     }
+
+    /// echo quits once the lock file is gone, closing its terminal
+    #[cfg(not(windows))]
+    pub fn kill(&mut self) -> Result<(), Box<dyn Error>> {
+        match remove_file(lock_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => { return Err(e.into()); },
+            _ => {}
+        }
+        if let Some(child) = self.child.as_mut() {
+            child.try_wait()?;
+        }
+        Ok(())
+    }
+}
+
+/// $TERMINAL followed by the known terminal emulators
+#[cfg(not(windows))]
+fn terminals() -> Vec<(String, Vec<&'static str>)> {
+    let mut terminals = Vec::new();
+    if let Some(terminal) = std::env::var_os("TERMINAL").filter(|terminal| !terminal.is_empty()) {
+        let terminal = terminal.to_string_lossy().to_string();
+        let name = PathBuf::from(&terminal).file_name().unwrap_or_default().to_string_lossy().to_string();
+        let args = TERMINALS.iter().find(|(known, _)| *known == name).map(|(_, args)| args.to_vec()).unwrap_or(vec!["-e"]);
+        terminals.push((terminal, args));
+    }
+    terminals.extend(TERMINALS.iter().map(|(terminal, args)| (terminal.to_string(), args.to_vec())));
+    terminals
+}
+
+/// lock file of the status file, held by echo
+#[cfg(not(windows))]
+fn lock_path() -> PathBuf {
+    PathBuf::from(format!("{}.lock", status_path().display()))
+}
+
+#[cfg(not(windows))]
+fn echo_running() -> bool {
+    match File::open(lock_path()) {
+        Ok(lock) => lock.try_lock_exclusive().is_err(), // the lock is released when the file is dropped
+        Err(_) => false
+    }
+}
+
+/// a progress line for every tenth of the files, the verification starts over
+fn progress_line(state: &ApplicationState, last: &mut Option<(bool, usize)>) -> Option<String> {
+    let (verifying, progress) = match state {
+        ApplicationState::BackingUp(progress) => (false, progress),
+        ApplicationState::Verifying(progress) => (true, progress),
+        _ => { return None; }
+    };
+    if progress.total_files == 0 {
+        return None;
+    }
+    let tenth = progress.files * 10 / progress.total_files;
+    if *last == Some((verifying, tenth)) || tenth == 0 {
+        return None;
+    }
+    *last = Some((verifying, tenth));
+    Some(format!("{} {}% ({} / {} files, {} / {})", if verifying { "Verified" } else { "Stored" }, tenth * 10,
+                 progress.files, progress.total_files, progress.size.human_readable(), progress.total_size.human_readable()))
 }
 
 /// will respawn the echo process if is closed externally, to quit just drop the sender
+/// besides the messages of the backup it shows its progress from the application state
 pub fn echo_main(rx: Receiver<String>) {
     let mut echo = Echo::new();
-    while let Ok(message) = rx.recv() {
-        if APP_STATE.read() == ApplicationState::Quit {
-            break;
-        }
+    let state_rx = APP_STATE.subscribe_sync();
+    let mut last_progress = None;
+    loop {
+        let message = select! {
+            recv(rx) -> message => match message {
+                Ok(message) => message,
+                Err(_) => { break; }
+            },
+            recv(state_rx) -> state => match state.ok().and_then(|state| progress_line(&state, &mut last_progress)) {
+                Some(line) => line,
+                None => { continue; }
+            }
+        };
         if !echo.check_alive() {
-            match echo.spawn() {
-                Err(e) => {
-                    error!("echo", format!("Fatal error! {:?}", e));
-                    tokio!().block_on(APP_STATE.change(ApplicationState::Quit));
-                    break;
-                },
-                _ => {}
+            // the console is not reopened while quitting, the backup is finishing its current file
+            if APP_STATE.read() == ApplicationState::Quit {
+                continue;
             }
-        }
-        match echo.write(message) {
-            Err(e) => {
-                error!("echo", format!("Fatal error! {:?}", e));
-                tokio!().block_on(APP_STATE.change(ApplicationState::Quit));
-                break;
+            if let Err(e) = echo.spawn() {
+                error!("echo", format!("Couldn't start echo! {:?}", e));
+                continue;
             }
-            _ => {}
+        }
+        // the status is lost on a write error, but the messages keep being received so the backup never blocks on them
+        if let Err(e) = echo.write(message) {
+            error!("echo", format!("Couldn't write the status! {:?}", e));
         }
     }
     drop(rx);
@@ -86,7 +287,7 @@ pub fn echo_main(rx: Receiver<String>) {
     match echo.kill() {
         Err(e) => {
             error!("echo", format!("Fatal error! {:?}", e));
-            tokio!().block_on(APP_STATE.change(ApplicationState::Quit));
+            APP_STATE.change(ApplicationState::Quit).ok();
         },
         Ok(_) => {
             info!("echo", "Echo killed.");
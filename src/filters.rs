@@ -0,0 +1,232 @@
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use glob::{MatchOptions, Pattern, PatternError};
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: !cfg!(windows),
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// a gitignore style exclude rule
+struct ExcludeRule {
+    pattern: Pattern,
+    /// `!pattern`, re-includes what a previous rule excluded
+    negated: bool,
+    /// `pattern/`, only matches directories
+    dir_only: bool,
+    /// the pattern contains a `/`, so it matches the path relative to the source root instead of the name
+    anchored: bool,
+}
+
+impl ExcludeRule {
+    /// returns None for blank lines and comments
+    fn parse(rule: &str) -> Result<Option<Self>, PatternError> {
+        let rule = rule.trim();
+        if rule.is_empty() || rule.starts_with('#') {
+            return Ok(None);
+        }
+        let (negated, rule) = match rule.strip_prefix('!') {
+            Some(rule) => (true, rule),
+            None => (false, rule)
+        };
+        let (dir_only, rule) = match rule.strip_suffix('/') {
+            Some(rule) => (true, rule),
+            None => (false, rule)
+        };
+        let anchored = rule.contains('/');
+        let rule = rule.trim_start_matches('/');
+        Ok(Some(Self {
+            pattern: Pattern::new(rule)?,
+            negated,
+            dir_only,
+            anchored
+        }))
+    }
+
+    fn matches(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.pattern.matches_path_with(relative, MATCH_OPTIONS)
+        } else {
+            relative.file_name().is_some_and(|name| self.pattern.matches_with(&name.to_string_lossy(), MATCH_OPTIONS))
+        }
+    }
+}
+
+/// decides which files of a source are backed up: exclude rules and size, age and extension filters
+pub struct Filters {
+    rules: Vec<ExcludeRule>,
+    /// the definition the filters were built from, part of the sources checksum
+    definition: String,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    /// files modified before this time (seconds since the unix epoch) are left out
+    min_modified: Option<u64>,
+    /// lowercase extensions, without the dot
+    extensions: Option<Vec<String>>,
+}
+
+impl Filters {
+    pub fn new(exclude: &[String], min_size: Option<u64>, max_size: Option<u64>, max_age_days: Option<u64>, extensions: Option<&Vec<String>>) -> Result<Self, PatternError> {
+        let mut rules = Vec::new();
+        for rule in exclude {
+            if let Some(rule) = ExcludeRule::parse(rule)? {
+                rules.push(rule);
+            }
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let definition = if exclude.is_empty() && min_size.is_none() && max_size.is_none() && max_age_days.is_none() && extensions.is_none() {
+            String::default() // keeps the checksum of plain sources unchanged
+        } else {
+            format!("{:?} {:?} {:?} {:?} {:?}", exclude, min_size, max_size, max_age_days, extensions)
+        };
+        Ok(Self {
+            rules,
+            definition,
+            min_size,
+            max_size,
+            min_modified: max_age_days.map(|days| now.saturating_sub(days * 24 * 60 * 60)),
+            extensions: extensions.map(|extensions| extensions.iter()
+                .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                .collect())
+        })
+    }
+
+    /// textual form of the filters definition
+    pub fn describe(&self) -> &str {
+        &self.definition
+    }
+
+    /// whether the exclude rules leave out a path relative to the source root, the last matching rule wins
+    pub fn excludes(&self, relative: &Path, is_dir: bool) -> bool {
+        let mut excluded = false;
+        for rule in self.rules.iter() {
+            if rule.matches(relative, is_dir) {
+                excluded = !rule.negated;
+            }
+        }
+        excluded
+    }
+
+    /// whether a path relative to the source root is excluded, either directly or because one of its parent folders is
+    pub fn excludes_with_parents(&self, relative: &Path, is_dir: bool) -> bool {
+        let mut parent = PathBuf::new();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            parent.push(component);
+            if components.peek().is_some() && self.excludes(&parent, true) {
+                return true;
+            }
+        }
+        self.excludes(relative, is_dir)
+    }
+
+    /// whether a file passes the size, age and extension filters
+    pub fn accepts(&self, path: &Path, size: u64, modified: u64) -> bool {
+        if self.min_size.is_some_and(|min_size| size < min_size) {
+            return false;
+        }
+        if self.max_size.is_some_and(|max_size| size > max_size) {
+            return false;
+        }
+        if self.min_modified.is_some_and(|min_modified| modified < min_modified) {
+            return false;
+        }
+        if let Some(extensions) = &self.extensions {
+            let extension = path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+            if !extensions.contains(&extension) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// folder a glob pattern starts from: its components up to the first one with wildcards
+pub fn glob_root(pattern: &str) -> PathBuf {
+    let mut root = PathBuf::new();
+    for component in Path::new(pattern).components() {
+        if let Component::Normal(name) = component {
+            if name.to_string_lossy().contains(['*', '?', '[']) {
+                break;
+            }
+        }
+        root.push(component);
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{Filters, glob_root};
+
+    fn with_rules(exclude: &[&str]) -> Filters {
+        let exclude: Vec<String> = exclude.iter().map(|rule| rule.to_string()).collect();
+        Filters::new(&exclude, None, None, None, None).unwrap()
+    }
+
+    #[test]
+    fn unanchored_rules_match_the_name_at_any_depth() {
+        let filters = with_rules(&["*.tmp", "# a comment", ""]);
+        assert!(filters.excludes(Path::new("a.tmp"), false));
+        assert!(filters.excludes(Path::new("src/deep/a.tmp"), false));
+        assert!(!filters.excludes(Path::new("a.tmp.txt"), false));
+    }
+
+    #[test]
+    fn anchored_rules_match_from_the_source_root() {
+        let filters = with_rules(&["/build", "docs/*.pdf"]);
+        assert!(filters.excludes(Path::new("build"), true));
+        assert!(!filters.excludes(Path::new("src/build"), true));
+        assert!(filters.excludes(Path::new("docs/a.pdf"), false));
+        assert!(!filters.excludes(Path::new("src/docs/a.pdf"), false));
+        // the wildcards don't cross folders
+        assert!(!filters.excludes(Path::new("docs/old/a.pdf"), false));
+    }
+
+    #[test]
+    fn dir_only_rules_skip_files() {
+        let filters = with_rules(&["target/"]);
+        assert!(filters.excludes(Path::new("target"), true));
+        assert!(!filters.excludes(Path::new("target"), false));
+        assert!(filters.excludes_with_parents(Path::new("target/debug/app"), false));
+        assert!(filters.excludes_with_parents(Path::new("crate/target/a.o"), false));
+    }
+
+    #[test]
+    fn negated_rules_re_include_and_the_last_match_wins() {
+        let filters = with_rules(&["*.log", "!keep.log"]);
+        assert!(filters.excludes(Path::new("a.log"), false));
+        assert!(!filters.excludes(Path::new("keep.log"), false));
+        let filters = with_rules(&["!keep.log", "*.log"]);
+        assert!(filters.excludes(Path::new("keep.log"), false));
+        // a file can't be re-included from an excluded folder
+        let filters = with_rules(&["logs/", "!keep.log"]);
+        assert!(filters.excludes_with_parents(Path::new("logs/keep.log"), false));
+    }
+
+    #[test]
+    fn size_age_and_extension_filters() {
+        let extensions = vec![".JPG".to_string(), "png".to_string()];
+        let filters = Filters::new(&[], Some(10), Some(100), Some(1), Some(&extensions)).unwrap();
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        assert!(filters.accepts(Path::new("a.jpg"), 50, now));
+        assert!(filters.accepts(Path::new("a.PNG"), 10, now));
+        assert!(!filters.accepts(Path::new("a.gif"), 50, now));
+        assert!(!filters.accepts(Path::new("a.jpg"), 5, now));
+        assert!(!filters.accepts(Path::new("a.jpg"), 500, now));
+        assert!(!filters.accepts(Path::new("a.jpg"), 50, now - 2 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn glob_root_stops_at_the_first_wildcard() {
+        assert_eq!(glob_root("/data/projects/**/*.rs"), PathBuf::from("/data/projects"));
+        assert_eq!(glob_root("/data/file.txt"), PathBuf::from("/data/file.txt"));
+    }
+}
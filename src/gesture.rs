@@ -0,0 +1,359 @@
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::screen::Screen;
+
+/// gestures available without defining them
+pub const BUILTIN_GESTURES: [&str; 4] = ["counterclockwise", "clockwise", "plus", "minus"];
+
+/// screen regions a gesture goes through, the edges don't include the corners
+#[derive(Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Region {
+    TopLeft,
+    Top,
+    TopRight,
+    Right,
+    BottomRight,
+    Bottom,
+    BottomLeft,
+    Left,
+    /// a square around the center of the screen, as wide as two margins
+    Center,
+}
+
+#[derive(Deserialize, PartialEq, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// the steps in the given order
+    #[default]
+    Forward,
+    /// the steps in reverse order
+    Reverse,
+    /// either of the two
+    Both,
+}
+
+/// a step is either a region or a region with its own timeout
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Step {
+    Region(Region),
+    Timed {
+        region: Region,
+        /// time allowed to reach this step from the previous one
+        timeout_ms: u64,
+    },
+}
+
+/// distance from the screen borders the regions extend to, a fraction of the screen width (`0.1`) or pixels (`"80px"`)
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Margin {
+    Fraction(f64),
+    Pixels(String),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GestureDefinition {
+    /// regions to go through, in order
+    pub steps: Vec<Step>,
+    #[serde(default)]
+    pub direction: Direction,
+    /// time allowed to reach each step from the previous one, unless the step sets its own
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// the margin of the [gesture] section if missing
+    #[serde(default)]
+    pub margin: Option<Margin>,
+    /// the gesture starts over whenever the mouse leaves the margin along the screen borders
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl Step {
+    fn region(&self) -> Region {
+        match self {
+            Step::Region(region) => *region,
+            Step::Timed { region, .. } => *region
+        }
+    }
+
+    fn timeout_ms(&self) -> Option<u64> {
+        match self {
+            Step::Region(_) => None,
+            Step::Timed { timeout_ms, .. } => Some(*timeout_ms)
+        }
+    }
+}
+
+impl Margin {
+    /// margin in pixels for a screen width
+    pub fn pixels(&self, width: u32) -> Result<u32, String> {
+        match self {
+            Margin::Fraction(fraction) if *fraction > 0.0 && *fraction < 0.5 => Ok((width as f64 * fraction) as u32),
+            Margin::Fraction(fraction) => Err(format!("margin must be between 0 and 0.5, found {}", fraction)),
+            Margin::Pixels(pixels) => pixels.strip_suffix("px")
+                .and_then(|pixels| pixels.trim().parse::<u32>().ok())
+                .filter(|pixels| *pixels > 0)
+                .ok_or(format!("margin must be a fraction of the screen width or pixels like \"80px\", found \"{}\"", pixels))
+        }
+    }
+}
+
+impl GestureDefinition {
+    /// the rectangles around the border of the screen, where the margin must never be left,
+    /// and the "+" and "-" strokes across the center, meant for confirming
+    pub fn builtin(name: &str) -> Option<Self> {
+        let (steps, direction, timeout_ms, strict) = match name {
+            "counterclockwise" => (vec![Region::TopLeft, Region::BottomLeft, Region::BottomRight, Region::TopRight], Direction::Forward, None, true),
+            "clockwise" => (vec![Region::TopLeft, Region::TopRight, Region::BottomRight, Region::BottomLeft], Direction::Forward, None, true),
+            "plus" => (vec![Region::Top, Region::Center, Region::Bottom, Region::Left, Region::Center, Region::Right], Direction::Forward, Some(1500), false),
+            "minus" => (vec![Region::Left, Region::Center, Region::Right], Direction::Both, Some(1000), false),
+            _ => { return None; }
+        };
+        Some(Self {
+            steps: steps.into_iter().map(Step::Region).collect(),
+            direction,
+            timeout_ms,
+            margin: None,
+            strict,
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.steps.len() < 2 {
+            return Err("at least two steps are needed".to_string());
+        }
+        if self.steps.windows(2).any(|steps| steps[0].region() == steps[1].region()) {
+            return Err("two consecutive steps have the same region".to_string());
+        }
+        if self.strict && self.steps.iter().any(|step| step.region() == Region::Center) {
+            return Err("a strict gesture can't go through the center".to_string());
+        }
+        if self.timeout_ms == Some(0) || self.steps.iter().any(|step| step.timeout_ms() == Some(0)) {
+            return Err("timeout_ms must be greater than 0".to_string());
+        }
+        if let Some(margin) = &self.margin {
+            margin.pixels(u32::MAX / 2)?; // only the format is checked, the screen is not known yet
+        }
+        Ok(())
+    }
+}
+
+/// the result of a mouse position for a gesture
+#[derive(PartialEq, Clone, Copy)]
+pub enum Progress {
+    /// nothing relevant happened
+    None,
+    /// the gesture started over: the margin was left by a strict gesture or a step timed out
+    Broken,
+    /// the last step was reached, the gesture starts over
+    Completed,
+}
+
+/// tracks a gesture definition on a screen
+pub struct Recognizer {
+    /// the steps for each accepted direction, with the time allowed to reach them
+    sequences: Vec<Vec<(Region, Option<Duration>)>>,
+    /// last step reached in each sequence, and when
+    reached: Vec<Option<(usize, Instant)>>,
+    strict: bool,
+    /// the area the gesture is drawn on
+    screen: Screen,
+    margin: i64,
+}
+
+impl Recognizer {
+    pub fn new(definition: &GestureDefinition, default_margin: f64, screen: Screen) -> Self {
+        let steps: Vec<(Region, Option<Duration>)> = definition.steps.iter()
+            .map(|step| (step.region(), step.timeout_ms().or(definition.timeout_ms).map(Duration::from_millis)))
+            .collect();
+        let reversed = || {
+            // each step keeps its timeout, now measured from the step that follows it in the definition
+            let mut reversed: Vec<(Region, Option<Duration>)> = steps.iter().rev().map(|(region, _)| (*region, None)).collect();
+            for (idx, (_, timeout)) in reversed.iter_mut().enumerate().skip(1) {
+                *timeout = steps[steps.len() - idx].1;
+            }
+            reversed
+        };
+        let sequences = match definition.direction {
+            Direction::Forward => vec![steps.clone()],
+            Direction::Reverse => vec![reversed()],
+            Direction::Both => vec![steps.clone(), reversed()],
+        };
+        let margin = definition.margin.as_ref()
+            .and_then(|margin| margin.pixels(screen.width).ok())
+            .unwrap_or((screen.width as f64 * default_margin) as u32);
+        Self {
+            reached: vec![None; sequences.len()],
+            sequences,
+            strict: definition.strict,
+            screen,
+            margin: margin as i64,
+        }
+    }
+
+    /// the gesture starts over
+    pub fn reset(&mut self) {
+        self.reached.iter_mut().for_each(|reached| *reached = None);
+    }
+
+    /// advances the gesture with a mouse position in virtual desktop coordinates
+    pub fn feed(&mut self, x: i32, y: i32) -> Progress {
+        let now = Instant::now();
+        let position = self.relative(x, y);
+
+        // leaving the screen counts as leaving the border
+        if self.strict && !position.is_some_and(|(x, y)| self.in_border(x, y)) {
+            self.reset();
+            return Progress::Broken;
+        }
+
+        let mut progress = Progress::None;
+        let region = position.and_then(|(x, y)| self.region(x, y));
+
+        for (sequence, reached) in self.sequences.iter().zip(self.reached.iter_mut()) {
+            let mut next = match reached {
+                // the time for the next step counts from when the mouse leaves the current one
+                Some((idx, at)) if region == Some(sequence[*idx].0) => { *at = now; *idx + 1 },
+                Some((idx, _)) => *idx + 1,
+                None => 0
+            };
+            // too late for the next step
+            if let (Some((_, at)), Some(timeout)) = (*reached, sequence[next].1) {
+                if now.duration_since(at) > timeout {
+                    *reached = None;
+                    next = 0;
+                    progress = Progress::Broken;
+                }
+            }
+            if region == Some(sequence[next].0) {
+                if next == sequence.len() - 1 {
+                    progress = Progress::Completed;
+                    break;
+                }
+                *reached = Some((next, now));
+            }
+        }
+
+        if progress == Progress::Completed {
+            self.reset();
+        }
+        progress
+    }
+
+    /// position relative to the top left corner of the screen, none if it's outside of it
+    fn relative(&self, x: i32, y: i32) -> Option<(i64, i64)> {
+        let (x, y) = (x as i64 - self.screen.x as i64, y as i64 - self.screen.y as i64);
+        if x < 0 || y < 0 || x >= self.screen.width as i64 || y >= self.screen.height as i64 {
+            return None;
+        }
+        Some((x, y))
+    }
+
+    fn in_border(&self, x: i64, y: i64) -> bool {
+        let (width, height) = (self.screen.width as i64, self.screen.height as i64);
+        x < self.margin || x > width - self.margin || y < self.margin || y > height - self.margin
+    }
+
+    fn region(&self, x: i64, y: i64) -> Option<Region> {
+        let (width, height) = (self.screen.width as i64, self.screen.height as i64);
+        let left = x < self.margin;
+        let right = x > width - self.margin;
+        let top = y < self.margin;
+        let bottom = y > height - self.margin;
+        let region = match (left, right, top, bottom) {
+            (true, _, true, _) => Region::TopLeft,
+            (_, true, true, _) => Region::TopRight,
+            (true, _, _, true) => Region::BottomLeft,
+            (_, true, _, true) => Region::BottomRight,
+            (true, _, _, _) => Region::Left,
+            (_, true, _, _) => Region::Right,
+            (_, _, true, _) => Region::Top,
+            (_, _, _, true) => Region::Bottom,
+            _ if (x - width / 2).abs() < self.margin && (y - height / 2).abs() < self.margin => Region::Center,
+            _ => { return None; }
+        };
+        Some(region)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::screen::Screen;
+
+    use super::{GestureDefinition, Progress, Recognizer, Region, Step};
+
+    /// on a 1000x1000 screen with a 100 px margin
+    fn recognizer(definition: &GestureDefinition) -> Recognizer {
+        Recognizer::new(definition, 0.1, Screen { x: 0, y: 0, width: 1000, height: 1000 })
+    }
+
+    /// the progress of the last position
+    fn feed(recognizer: &mut Recognizer, positions: &[(i32, i32)]) -> Progress {
+        positions.iter().fold(Progress::None, |_, (x, y)| recognizer.feed(*x, *y))
+    }
+
+    const TOP_LEFT: (i32, i32) = (10, 10);
+    const TOP_RIGHT: (i32, i32) = (990, 10);
+    const BOTTOM_LEFT: (i32, i32) = (10, 990);
+    const BOTTOM_RIGHT: (i32, i32) = (990, 990);
+    const LEFT: (i32, i32) = (10, 500);
+    const RIGHT: (i32, i32) = (990, 500);
+    const CENTER: (i32, i32) = (500, 500);
+
+    #[test]
+    fn completes_through_the_steps() {
+        let mut recognizer = recognizer(&GestureDefinition::builtin("counterclockwise").unwrap());
+        assert!(feed(&mut recognizer, &[TOP_LEFT, LEFT, BOTTOM_LEFT, BOTTOM_RIGHT]) == Progress::None);
+        assert!(recognizer.feed(TOP_RIGHT.0, TOP_RIGHT.1) == Progress::Completed);
+        // it starts over once completed
+        assert!(recognizer.feed(TOP_RIGHT.0, TOP_RIGHT.1) == Progress::None);
+    }
+
+    #[test]
+    fn leaving_the_margin_breaks_a_strict_gesture() {
+        let mut recognizer = recognizer(&GestureDefinition::builtin("counterclockwise").unwrap());
+        assert!(feed(&mut recognizer, &[TOP_LEFT, BOTTOM_LEFT]) == Progress::None);
+        assert!(recognizer.feed(CENTER.0, CENTER.1) == Progress::Broken);
+        assert!(feed(&mut recognizer, &[BOTTOM_RIGHT, TOP_RIGHT]) == Progress::None);
+        // leaving the screen breaks it too
+        assert!(feed(&mut recognizer, &[TOP_LEFT, BOTTOM_LEFT]) == Progress::None);
+        assert!(recognizer.feed(-10, 990) == Progress::Broken);
+    }
+
+    #[test]
+    fn both_directions() {
+        let mut recognizer = recognizer(&GestureDefinition::builtin("minus").unwrap());
+        assert!(feed(&mut recognizer, &[LEFT, CENTER, RIGHT]) == Progress::Completed);
+        assert!(feed(&mut recognizer, &[RIGHT, CENTER, LEFT]) == Progress::Completed);
+        // a loose gesture ignores the positions outside its regions
+        assert!(feed(&mut recognizer, &[LEFT, (300, 300), CENTER, (700, 300), RIGHT]) == Progress::Completed);
+    }
+
+    #[test]
+    fn a_late_step_breaks_the_gesture() {
+        let definition = GestureDefinition {
+            steps: vec![Step::Region(Region::Left), Step::Timed { region: Region::Center, timeout_ms: 50 }, Step::Region(Region::Right)],
+            direction: Default::default(),
+            timeout_ms: None,
+            margin: None,
+            strict: false,
+        };
+        let mut recognizer = recognizer(&definition);
+        assert!(recognizer.feed(LEFT.0, LEFT.1) == Progress::None);
+        thread::sleep(Duration::from_millis(100));
+        assert!(recognizer.feed(CENTER.0, CENTER.1) == Progress::Broken);
+        assert!(recognizer.feed(RIGHT.0, RIGHT.1) == Progress::None);
+        // lingering in a step doesn't count, the time runs from when it's left
+        assert!(recognizer.feed(LEFT.0, LEFT.1) == Progress::None);
+        thread::sleep(Duration::from_millis(100));
+        assert!(feed(&mut recognizer, &[LEFT, CENTER, RIGHT]) == Progress::Completed);
+    }
+}
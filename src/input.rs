@@ -0,0 +1,128 @@
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam::channel::{bounded, Receiver, Sender, TrySendError};
+use lazy_static::lazy_static;
+use mouse_position::mouse_position::Mouse;
+use rdev::{EventType, Key, listen};
+use serde::Deserialize;
+
+use crate::logger::{error, info};
+use crate::state::{APP_STATE, ApplicationState};
+
+/// where the input comes from
+#[derive(Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum InputBackend {
+    /// os input events, the listeners only wake up when the input changes
+    Events,
+    /// the mouse position is probed at fixed intervals, for when the events are not available
+    Polling,
+}
+
+impl fmt::Display for InputBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let str = match self {
+            InputBackend::Events => "events",
+            InputBackend::Polling => "polling",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum InputEvent {
+    MouseMove { x: i32, y: i32 },
+    /// keys are only available with the events backend
+    KeyPress(Key),
+    KeyRelease(Key),
+}
+
+/// events that arrive while a listener is busy (e.g. during a backup) are dropped, they would be stale anyway
+const QUEUE_SIZE: usize = 64;
+/// time the events backend has to fail before it's considered running, since on success it never returns
+const LISTEN_GRACE: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    static ref SUBSCRIBERS: Mutex<Vec<Sender<InputEvent>>> = Mutex::new(Vec::new());
+}
+static BACKEND: OnceLock<InputBackend> = OnceLock::new();
+
+/// backend in use, none until the input is started
+pub fn backend() -> Option<InputBackend> {
+    BACKEND.get().copied()
+}
+
+/// channel of the input events, they arrive once the input is started
+pub fn subscribe() -> Receiver<InputEvent> {
+    let (tx, rx) = bounded(QUEUE_SIZE);
+    SUBSCRIBERS.lock().unwrap().push(tx);
+    rx
+}
+
+fn publish(event: InputEvent) {
+    SUBSCRIBERS.lock().unwrap().retain(|tx| !matches!(tx.try_send(event), Err(TrySendError::Disconnected(_))));
+}
+
+/// starts the preferred backend, falling back to polling when the os events are not available
+/// the os events can only be listened once per process, so every subscriber shares the same backend
+pub fn start(preferred: InputBackend, probe_interval: Duration) -> InputBackend {
+    *BACKEND.get_or_init(|| {
+        if preferred == InputBackend::Events && start_events() {
+            return InputBackend::Events;
+        }
+        start_polling(probe_interval);
+        InputBackend::Polling
+    })
+}
+
+/// returns false if the listener failed to start
+fn start_events() -> bool {
+    let (failed_tx, failed_rx) = bounded(1);
+    thread::spawn(move || {
+        // blocks for the whole life of the process
+        let result = listen(|event| {
+            match event.event_type {
+                EventType::MouseMove { x, y } => { publish(InputEvent::MouseMove { x: x as i32, y: y as i32 }); },
+                EventType::KeyPress(key) => { publish(InputEvent::KeyPress(key)); },
+                EventType::KeyRelease(key) => { publish(InputEvent::KeyRelease(key)); },
+                _ => {}
+            }
+        });
+        if let Err(e) = result {
+            error!("input", format!("Cannot listen to input events! {:?}", e));
+            failed_tx.send(()).ok();
+        }
+    });
+    failed_rx.recv_timeout(LISTEN_GRACE).is_err()
+}
+
+fn start_polling(probe_interval: Duration) {
+    info!("input", "Probing the mouse position.");
+    thread::spawn(move || {
+        let mut last = None;
+        loop {
+            if APP_STATE.read() == ApplicationState::Quit {
+                break;
+            }
+            match Mouse::get_mouse_position() {
+                Mouse::Position { x, y } => {
+                    // only movements are published, like the os events
+                    if last != Some((x, y)) {
+                        last = Some((x, y));
+                        publish(InputEvent::MouseMove { x, y });
+                    }
+                },
+                Mouse::Error => {
+                    error!("input", "Error getting mouse position!");
+                    break;
+                },
+            }
+            // this is a low level loop, we have no other choice than to sleep the thread
+            // at least we can choose the update interval, and in this case it can be very long
+            thread::sleep(probe_interval);
+        }
+    });
+}
@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use rdev::Key;
+
+use crate::config::Config;
+use crate::gesture::Progress;
+use crate::input::InputEvent;
+use crate::logger::info;
+use crate::trigger::{Phase, Signal, Trigger};
+
+/// keys held together, each group is satisfied by any of its keys (e.g. left or right control)
+type Chord = Vec<Vec<Key>>;
+
+/// parses a chord like `Ctrl+Alt+B`
+pub fn parse_chord(chord: &str) -> Result<Chord, String> {
+    chord.split('+')
+        .map(|key| parse_key(key.trim()).ok_or(format!("unknown key '{}' in '{}'", key.trim(), chord)))
+        .collect()
+}
+
+fn parse_key(key: &str) -> Option<Vec<Key>> {
+    let keys = match key.to_lowercase().as_str() {
+        "ctrl" | "control" => vec![Key::ControlLeft, Key::ControlRight],
+        "shift" => vec![Key::ShiftLeft, Key::ShiftRight],
+        "alt" => vec![Key::Alt],
+        "altgr" => vec![Key::AltGr],
+        "super" | "win" | "meta" | "cmd" => vec![Key::MetaLeft, Key::MetaRight],
+        "esc" | "escape" => vec![Key::Escape],
+        "enter" | "return" => vec![Key::Return, Key::KpReturn],
+        "space" => vec![Key::Space],
+        "tab" => vec![Key::Tab],
+        "backspace" => vec![Key::Backspace],
+        "delete" | "del" => vec![Key::Delete],
+        "insert" | "ins" => vec![Key::Insert],
+        "home" => vec![Key::Home],
+        "end" => vec![Key::End],
+        "pageup" => vec![Key::PageUp],
+        "pagedown" => vec![Key::PageDown],
+        "up" => vec![Key::UpArrow],
+        "down" => vec![Key::DownArrow],
+        "left" => vec![Key::LeftArrow],
+        "right" => vec![Key::RightArrow],
+        "pause" => vec![Key::Pause],
+        "printscreen" => vec![Key::PrintScreen],
+        "scrolllock" => vec![Key::ScrollLock],
+        "minus" | "-" => vec![Key::Minus, Key::KpMinus],
+        "plus" => vec![Key::Equal, Key::KpPlus], // the plus sign shares the key with equal on most layouts
+        "f1" => vec![Key::F1],
+        "f2" => vec![Key::F2],
+        "f3" => vec![Key::F3],
+        "f4" => vec![Key::F4],
+        "f5" => vec![Key::F5],
+        "f6" => vec![Key::F6],
+        "f7" => vec![Key::F7],
+        "f8" => vec![Key::F8],
+        "f9" => vec![Key::F9],
+        "f10" => vec![Key::F10],
+        "f11" => vec![Key::F11],
+        "f12" => vec![Key::F12],
+        other => {
+            let mut chars = other.chars();
+            let (Some(char), None) = (chars.next(), chars.next()) else { return None; };
+            vec![match char {
+                'a' => Key::KeyA, 'b' => Key::KeyB, 'c' => Key::KeyC, 'd' => Key::KeyD, 'e' => Key::KeyE,
+                'f' => Key::KeyF, 'g' => Key::KeyG, 'h' => Key::KeyH, 'i' => Key::KeyI, 'j' => Key::KeyJ,
+                'k' => Key::KeyK, 'l' => Key::KeyL, 'm' => Key::KeyM, 'n' => Key::KeyN, 'o' => Key::KeyO,
+                'p' => Key::KeyP, 'q' => Key::KeyQ, 'r' => Key::KeyR, 's' => Key::KeyS, 't' => Key::KeyT,
+                'u' => Key::KeyU, 'v' => Key::KeyV, 'w' => Key::KeyW, 'x' => Key::KeyX, 'y' => Key::KeyY,
+                'z' => Key::KeyZ,
+                '0' => Key::Num0, '1' => Key::Num1, '2' => Key::Num2, '3' => Key::Num3, '4' => Key::Num4,
+                '5' => Key::Num5, '6' => Key::Num6, '7' => Key::Num7, '8' => Key::Num8, '9' => Key::Num9,
+                _ => { return None; }
+            }]
+        }
+    };
+    Some(keys)
+}
+
+/// chords pressed one after the other, each within the timeout from the previous one
+pub struct Sequence {
+    chords: Vec<Chord>,
+    timeout: Duration,
+    /// chords completed so far, and when the last one was
+    reached: Option<(usize, Instant)>,
+}
+
+impl Sequence {
+    pub fn new(chords: &[String], timeout: Duration) -> Result<Self, String> {
+        if chords.is_empty() {
+            return Err("empty key sequence".to_string());
+        }
+        Ok(Self {
+            chords: chords.iter().map(|chord| parse_chord(chord)).collect::<Result<_, _>>()?,
+            timeout,
+            reached: None,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.reached = None;
+    }
+
+    /// advances the sequence with a key press, given the keys held down including the pressed one
+    fn press(&mut self, key: Key, pressed: &HashSet<Key>) -> Progress {
+        let now = Instant::now();
+        let completes = |chord: &Chord| chord.iter().any(|group| group.contains(&key))
+            && chord.iter().all(|group| group.iter().any(|key| pressed.contains(key)));
+
+        let mut progress = Progress::None;
+        let mut next = self.reached.map(|(idx, _)| idx).unwrap_or(0);
+        if let Some((_, at)) = self.reached {
+            if now.duration_since(at) > self.timeout {
+                // too late for the next chord
+                self.reached = None;
+                next = 0;
+                progress = Progress::Broken;
+            }
+        }
+
+        if completes(&self.chords[next]) {
+            if next + 1 == self.chords.len() {
+                self.reached = None;
+                return Progress::Completed;
+            }
+            self.reached = Some((next + 1, now));
+        } else if next > 0 && !self.chords[next].iter().any(|group| group.contains(&key)) {
+            // a key out of the sequence, unless it's the start of a new one
+            self.reached = None;
+            progress = Progress::Broken;
+            if completes(&self.chords[0]) {
+                self.reached = Some((1, now));
+            }
+        }
+        progress
+    }
+}
+
+/// arms, confirms and aborts backups with key sequences
+pub struct KeyboardTrigger {
+    /// arming sequences with their profile
+    hotkeys: Vec<(Sequence, String)>,
+    armed: Option<usize>,
+    confirm: Option<Sequence>,
+    abort: Option<Sequence>,
+    /// keys currently held down
+    pressed: HashSet<Key>,
+}
+
+impl KeyboardTrigger {
+    /// none if no hotkey is bound, the configuration is already validated
+    pub fn new(config: &Config) -> Option<Self> {
+        let timeout = Duration::from_millis(config.hotkey.step_timeout_ms);
+        let sequence = |chords: &Vec<String>| Sequence::new(chords, timeout).ok();
+        let hotkeys: Vec<(Sequence, String)> = config.hotkeys().into_iter()
+            .filter_map(|(chords, profile)| sequence(&chords).map(|sequence| (sequence, profile)))
+            .collect();
+        if hotkeys.is_empty() {
+            return None;
+        }
+        for (chords, profile) in config.hotkeys() {
+            info!("keyboard", format!("Hotkey {} arms profile {}.", chords.join(", "), profile));
+        }
+        Some(Self {
+            hotkeys,
+            armed: None,
+            confirm: sequence(&config.hotkey.confirm),
+            abort: sequence(&config.hotkey.abort),
+            pressed: HashSet::new(),
+        })
+    }
+}
+
+impl Trigger for KeyboardTrigger {
+    fn name(&self) -> &'static str {
+        "keyboard"
+    }
+
+    fn feed(&mut self, event: &InputEvent, phase: Phase) -> Signal {
+        let key = match event {
+            InputEvent::KeyPress(key) => *key,
+            InputEvent::KeyRelease(key) => {
+                self.pressed.remove(key);
+                return Signal::None;
+            },
+            _ => { return Signal::None; }
+        };
+        // held keys repeat their press
+        if !self.pressed.insert(key) {
+            return Signal::None;
+        }
+
+        match phase {
+            Phase::Idle => {
+                for (idx, (sequence, profile)) in self.hotkeys.iter_mut().enumerate() {
+                    if sequence.press(key, &self.pressed) == Progress::Completed {
+                        self.armed = Some(idx);
+                        return Signal::Arm(profile.clone());
+                    }
+                }
+                Signal::None
+            },
+            Phase::Armed => {
+                let progress = match (self.confirm.as_mut(), self.armed) {
+                    (Some(confirm), _) => confirm.press(key, &self.pressed),
+                    (None, Some(idx)) => self.hotkeys[idx].0.press(key, &self.pressed),
+                    (None, None) => Progress::None
+                };
+                match progress {
+                    Progress::Completed => Signal::Confirm,
+                    Progress::Broken => Signal::Disarm,
+                    Progress::None => Signal::None
+                }
+            },
+            Phase::Running => {
+                if self.abort.as_mut().is_some_and(|abort| abort.press(key, &self.pressed) == Progress::Completed) {
+                    Signal::Abort
+                } else {
+                    Signal::None
+                }
+            },
+            Phase::Waiting | Phase::Paused => Signal::None
+        }
+    }
+
+    fn reset(&mut self) {
+        for (sequence, _) in self.hotkeys.iter_mut() {
+            sequence.reset();
+        }
+        if let Some(confirm) = self.confirm.as_mut() {
+            confirm.reset();
+        }
+        if let Some(abort) = self.abort.as_mut() {
+            abort.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::thread;
+    use std::time::Duration;
+
+    use rdev::Key;
+
+    use crate::gesture::Progress;
+    use crate::input::InputEvent;
+    use crate::trigger::{Phase, Signal, Trigger};
+
+    use super::{KeyboardTrigger, Sequence};
+
+    fn sequence(chords: &[&str], timeout_ms: u64) -> Sequence {
+        let chords: Vec<String> = chords.iter().map(|chord| chord.to_string()).collect();
+        Sequence::new(&chords, Duration::from_millis(timeout_ms)).unwrap()
+    }
+
+    /// presses a key, then releases the keys given, returns the progress of the press
+    fn press(sequence: &mut Sequence, pressed: &mut HashSet<Key>, key: Key, released: &[Key]) -> Progress {
+        pressed.insert(key);
+        let progress = sequence.press(key, pressed);
+        released.iter().for_each(|key| { pressed.remove(key); });
+        progress
+    }
+
+    #[test]
+    fn completes_a_chord_in_any_order() {
+        let mut sequence = sequence(&["Ctrl+Alt+B"], 1000);
+        let mut pressed = HashSet::new();
+        assert!(press(&mut sequence, &mut pressed, Key::KeyB, &[]) == Progress::None);
+        assert!(press(&mut sequence, &mut pressed, Key::Alt, &[]) == Progress::None);
+        assert!(press(&mut sequence, &mut pressed, Key::ControlRight, &[]) == Progress::Completed);
+    }
+
+    #[test]
+    fn completes_the_chords_one_after_the_other() {
+        let mut sequence = sequence(&["Ctrl+B", "Enter"], 1000);
+        let mut pressed = HashSet::new();
+        assert!(press(&mut sequence, &mut pressed, Key::ControlLeft, &[]) == Progress::None);
+        assert!(press(&mut sequence, &mut pressed, Key::KeyB, &[Key::ControlLeft, Key::KeyB]) == Progress::None);
+        assert!(press(&mut sequence, &mut pressed, Key::KpReturn, &[Key::KpReturn]) == Progress::Completed);
+        // it starts over once completed
+        assert!(press(&mut sequence, &mut pressed, Key::Return, &[Key::Return]) == Progress::None);
+    }
+
+    #[test]
+    fn a_key_out_of_the_sequence_breaks_it() {
+        let mut sequence = sequence(&["Ctrl+B", "Enter"], 1000);
+        let mut pressed = HashSet::new();
+        press(&mut sequence, &mut pressed, Key::ControlLeft, &[]);
+        assert!(press(&mut sequence, &mut pressed, Key::KeyB, &[Key::ControlLeft, Key::KeyB]) == Progress::None);
+        assert!(press(&mut sequence, &mut pressed, Key::KeyX, &[Key::KeyX]) == Progress::Broken);
+        assert!(press(&mut sequence, &mut pressed, Key::Return, &[Key::Return]) == Progress::None);
+    }
+
+    #[test]
+    fn the_first_chord_starts_the_sequence_over() {
+        let mut sequence = sequence(&["Ctrl+B", "Enter"], 1000);
+        let mut pressed = HashSet::new();
+        press(&mut sequence, &mut pressed, Key::ControlLeft, &[]);
+        press(&mut sequence, &mut pressed, Key::KeyB, &[Key::KeyB]);
+        assert!(press(&mut sequence, &mut pressed, Key::KeyB, &[Key::ControlLeft, Key::KeyB]) == Progress::Broken);
+        assert!(press(&mut sequence, &mut pressed, Key::Return, &[Key::Return]) == Progress::Completed);
+    }
+
+    #[test]
+    fn a_late_chord_breaks_the_sequence() {
+        let mut sequence = sequence(&["Ctrl+B", "Enter"], 50);
+        let mut pressed = HashSet::new();
+        press(&mut sequence, &mut pressed, Key::ControlLeft, &[]);
+        press(&mut sequence, &mut pressed, Key::KeyB, &[Key::ControlLeft, Key::KeyB]);
+        thread::sleep(Duration::from_millis(100));
+        assert!(press(&mut sequence, &mut pressed, Key::Return, &[Key::Return]) == Progress::Broken);
+    }
+
+    #[test]
+    fn keys_released_while_paused_are_not_held() {
+        let mut trigger = KeyboardTrigger {
+            hotkeys: vec![(sequence(&["Ctrl+B"], 1000), "default".to_string())],
+            armed: None,
+            confirm: None,
+            abort: None,
+            pressed: HashSet::new(),
+        };
+        assert!(matches!(trigger.feed(&InputEvent::KeyPress(Key::ControlLeft), Phase::Paused), Signal::None));
+        assert!(matches!(trigger.feed(&InputEvent::KeyPress(Key::KeyB), Phase::Paused), Signal::None));
+        trigger.feed(&InputEvent::KeyRelease(Key::ControlLeft), Phase::Paused);
+        trigger.feed(&InputEvent::KeyRelease(Key::KeyB), Phase::Paused);
+        assert!(matches!(trigger.feed(&InputEvent::KeyPress(Key::KeyB), Phase::Idle), Signal::None));
+        trigger.feed(&InputEvent::KeyRelease(Key::KeyB), Phase::Idle);
+        trigger.feed(&InputEvent::KeyPress(Key::ControlLeft), Phase::Idle);
+        assert!(matches!(trigger.feed(&InputEvent::KeyPress(Key::KeyB), Phase::Idle), Signal::Arm(profile) if profile == "default"));
+    }
+}
@@ -8,6 +8,7 @@ use std::sync::OnceLock;
 use std::thread;
 use std::time::Duration;
 
+use clap::Parser;
 use lazy_static::lazy_static;
 use native_dialog::{MessageDialog, MessageType};
 use sysinfo::System;
@@ -15,19 +16,38 @@ use tokio::time;
 
 use single_instance::SingleInstance;
 
-use crate::logger::info;
-use crate::mouse::mouse_main;
+use crate::cli::{Cli, Command};
+use crate::control::control_main;
+use crate::logger::{error, info};
 use crate::state::{APP_STATE, ApplicationState};
 use crate::tray::tray_main;
+use crate::trigger::trigger_main;
 
 mod logger;
 mod single_instance;
 mod tray;
 mod mouse;
+mod keyboard;
+mod trigger;
 mod audio;
 mod backup;
+mod manifest;
 mod echo;
 mod state;
+mod cli;
+mod control;
+mod config;
+mod filters;
+mod gesture;
+mod input;
+mod screen;
+mod restore;
+mod retention;
+mod verify;
+mod usage;
+
+/// how long quitting waits for a running backup to stop
+const QUIT_TIMEOUT: Duration = Duration::from_secs(30);
 
 lazy_static! {
     pub static ref TOKIO : OnceLock<tokio::runtime::Handle> = OnceLock::new();
@@ -45,6 +65,17 @@ async fn main() -> ExitCode {
 
     // prologue
 
+    TOKIO.set(tokio::runtime::Handle::current()).unwrap(); // commands play sounds too
+
+    if std::env::args_os().len() > 1 {
+        cli::attach_console(); // before parsing so that usage errors are visible
+    }
+    let cli = Cli::parse();
+    match cli.command {
+        None | Some(Command::Daemon) => {},
+        Some(command) => { return cli::run(command, cli.config); }
+    }
+
     let _single_instance = SingleInstance::new("blackout");
     if _single_instance.is_err() {
         MessageDialog::new()
@@ -60,9 +91,23 @@ async fn main() -> ExitCode {
 
     info!("main", "Process started.");
 
-    // tokio
-
-    TOKIO.set(tokio::runtime::Handle::current()).unwrap();
+    // config
+
+    match config::init(cli.config.as_deref()) {
+        Ok(Some(path)) => { info!("main", format!("Loaded configuration from {}", path.display())); },
+        Ok(None) => { info!("main", "No configuration file found, using defaults and sources.txt."); },
+        Err(e) => {
+            error!("main", e.clone());
+            MessageDialog::new()
+                .set_type(MessageType::Error)
+                .set_title("Blackout")
+                .set_text(&e)
+                .show_alert()
+                .unwrap();
+            logger::flush!();
+            return ExitCode::FAILURE
+        }
+    }
 
     // state
 
@@ -72,16 +117,24 @@ async fn main() -> ExitCode {
 
     let tray = thread::spawn(tray_main);
 
-    // mouse
+    // trigger
+
+    let trigger = thread::spawn(trigger_main);
 
-    let mouse = thread::spawn(mouse_main);
+    // control, not joined since it blocks on the endpoint
+
+    thread::spawn(control_main);
 
     // cpu
 
-    let two_mins = Duration::from_secs(120);
+    let cpu_interval = Duration::from_secs(config::get().logging.cpu_interval_secs);
     let mut system = System::new_all();
     let pid = (std::process::id() as usize).into();
     let num_cores = system.cpus().len();
+    // outside of the loop, so that the state changes (e.g. the backup progress) don't postpone it
+    let mut cpu_timer = time::interval(cpu_interval);
+    cpu_timer.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    cpu_timer.reset(); // the first tick is one interval away, as before
 
     // main thread
 
@@ -93,19 +146,24 @@ async fn main() -> ExitCode {
                     _ => {}
                 };
             },
-            _ = time::sleep(two_mins) => { // wake every 2 minutes
+            _ = cpu_timer.tick() => { // wake every cpu log interval (2 minutes by default)
                 // cpu usage log
                 system.refresh_cpu_usage();
                 let mut cpu_usage = system.process(pid).unwrap().cpu_usage();
                 cpu_usage /= num_cores as f32;
-                info!("main", format!("CPU usage: {:.2}%", cpu_usage));
+                // the input backend is the main factor when idle
+                let backend = input::backend().map(|backend| backend.to_string()).unwrap_or("none".to_string());
+                info!("main", format!("CPU usage: {:.2}% (input: {})", cpu_usage, backend));
             }
         }
     }
 
+    // a running backup stops after its current file, so that its snapshot is usable
+    backup::cancel_and_wait(QUIT_TIMEOUT);
+
     // join other threads
     tray.join().unwrap();
-    mouse.join().unwrap();
+    trigger.join().unwrap();
 
     // epilogue
 
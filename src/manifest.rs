@@ -0,0 +1,321 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs::{File, metadata, read_dir, remove_file, rename, set_permissions};
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use sysinfo::System;
+
+use crate::config::DEFAULT_PROFILE;
+
+/// suffix of the folder, log and manifest of a snapshot while it is written, they are renamed into place when it's done
+pub const PARTIAL: &str = ".partial";
+
+/// outcome of the copy of a file, tells how its content is stored in the snapshot
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum Outcome {
+    /// the file was copied into the snapshot
+    Copied,
+    /// the file was unchanged and has been hard linked from a previous snapshot
+    Linked,
+    /// the file was unchanged, its content lives in the referenced snapshot
+    Referenced { snapshot: String },
+    /// the file could not be stored
+    Failed { error: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Permissions {
+    pub readonly: bool,
+    /// unix permission bits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+}
+
+impl From<std::fs::Permissions> for Permissions {
+    fn from(permissions: std::fs::Permissions) -> Self {
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(permissions.mode())
+        };
+        #[cfg(not(unix))]
+        let mode = None;
+        Self {
+            readonly: permissions.readonly(),
+            mode
+        }
+    }
+}
+
+impl Permissions {
+    /// sets these permissions on a file
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        let mut permissions = metadata(path)?.permissions();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            match self.mode {
+                Some(mode) => permissions.set_mode(mode),
+                None => permissions.set_readonly(self.readonly)
+            }
+        }
+        #[cfg(not(unix))]
+        permissions.set_readonly(self.readonly);
+        set_permissions(path, permissions)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ManifestEntry {
+    /// path of the file inside the snapshot folder
+    pub path: PathBuf,
+    /// original absolute path of the file, including the drive or root
+    pub source: PathBuf,
+    /// size in bytes
+    pub size: u64,
+    /// modification time of the source, in seconds since the unix epoch
+    pub modified: u64,
+    pub permissions: Permissions,
+    /// md5 of the content, missing if the file could not be read
+    pub hash: Option<String>,
+    pub outcome: Outcome,
+    /// whether the stored content was read back and matched the hash, missing if it was not checked
+    #[serde(default)]
+    pub verified: Option<bool>,
+}
+
+/// machine readable description of a snapshot, saved as `<snapshot>.json` next to the snapshot folder
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    /// version of blackout that wrote the snapshot
+    pub version: String,
+    pub snapshot: String,
+    /// backup profile the snapshot was taken with
+    #[serde(default = "default_profile")]
+    pub profile: String,
+    pub host: String,
+    /// checksum of the sources definition the snapshot was taken from
+    pub sources_checksum: String,
+    /// rfc3339 timestamps of the start and of the end of the copy
+    pub started: String,
+    pub finished: String,
+    /// first folders of the snapshot layout mapped to the original roots they stand for, e.g. `C` -> `C:\`
+    #[serde(default)]
+    pub roots: BTreeMap<String, PathBuf>,
+    /// the backup was cancelled, the files after the last entry are missing
+    #[serde(default)]
+    pub incomplete: bool,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl ManifestEntry {
+    /// name of the snapshot folder that physically holds the content of this entry
+    pub fn holder<'a>(&'a self, snapshot: &'a str) -> &'a str {
+        match &self.outcome {
+            Outcome::Referenced { snapshot } => snapshot,
+            _ => snapshot
+        }
+    }
+
+    /// whether the content of the file is available in the backup
+    pub fn is_stored(&self) -> bool {
+        !matches!(self.outcome, Outcome::Failed { .. })
+    }
+}
+
+impl Manifest {
+    pub fn new(snapshot: String, profile: String, sources_checksum: String) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            snapshot,
+            profile,
+            host: System::host_name().unwrap_or_default(),
+            sources_checksum,
+            started: Local::now().to_rfc3339(),
+            finished: String::default(),
+            roots: BTreeMap::new(),
+            incomplete: false,
+            entries: Vec::new()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(())
+    }
+
+    /// stored entries indexed by their source path, the ones that failed a later verification are left out
+    pub fn by_source(&self) -> HashMap<&Path, &ManifestEntry> {
+        self.entries.iter().filter(|entry| entry.is_stored() && entry.verified != Some(false)).map(|entry| (entry.source.as_path(), entry)).collect()
+    }
+}
+
+/// path of the manifest of a snapshot
+pub fn manifest_path(backup_root: &Path, snapshot: &str) -> PathBuf {
+    backup_root.join(format!("{}.json", snapshot))
+}
+
+/// path of the log of a snapshot
+pub fn log_path(backup_root: &Path, snapshot: &str) -> PathBuf {
+    backup_root.join(format!("{}.log", snapshot))
+}
+
+/// name of the folder, log and manifest of a snapshot while it is written
+pub fn staging(snapshot: &str) -> String {
+    format!("{}{}", snapshot, PARTIAL)
+}
+
+/// path of the journal of a snapshot while it is written
+pub fn journal_path(backup_root: &Path, snapshot: &str) -> PathBuf {
+    backup_root.join(format!("{}.journal", staging(snapshot)))
+}
+
+/// moves a written snapshot into place: the folder, the log and last the manifest, which is what makes it a snapshot
+/// the files must be synced already, a crash in between leaves a snapshot without manifest that is never listed
+pub fn commit(backup_root: &Path, snapshot: &str) -> io::Result<()> {
+    let staging = staging(snapshot);
+    rename(backup_root.join(&staging), backup_root.join(snapshot))?;
+    rename(log_path(backup_root, &staging), log_path(backup_root, snapshot))?;
+    // the staged manifest holds everything the journal does
+    remove_file(journal_path(backup_root, snapshot))?;
+    sync_dir(backup_root)?;
+    rename(manifest_path(backup_root, &staging), manifest_path(backup_root, snapshot))?;
+    sync_dir(backup_root)
+}
+
+/// moves a snapshot back to its staging name so that it can be written again, the manifest first,
+/// whatever an interrupted commit already moved into place goes back as well
+pub fn reopen(backup_root: &Path, snapshot: &str) -> io::Result<()> {
+    let staging = staging(snapshot);
+    let moves = [
+        (manifest_path(backup_root, snapshot), manifest_path(backup_root, &staging)),
+        (backup_root.join(snapshot), backup_root.join(&staging)),
+        (log_path(backup_root, snapshot), log_path(backup_root, &staging)),
+    ];
+    for (committed, staged) in moves {
+        if committed.exists() {
+            rename(committed, staged)?;
+            sync_dir(backup_root)?;
+        }
+    }
+    Ok(())
+}
+
+/// makes the renames in a folder durable, windows has no way to sync a folder and commits the renames itself
+fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(path)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// names of the snapshots left in their staging files by a backup that was interrupted, oldest first
+pub fn partials(backup_root: &Path) -> Vec<String> {
+    let partials: BTreeSet<String> = match read_dir(backup_root) {
+        Err(_) => BTreeSet::new(),
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.file_name().to_string_lossy().split_once(PARTIAL).map(|(snapshot, _)| snapshot.to_string()))
+            .collect()
+    };
+    partials.into_iter().collect()
+}
+
+/// names of the snapshots in the backup root that have a manifest, oldest first
+pub fn snapshots(backup_root: &Path) -> Vec<String> {
+    let mut snapshots: Vec<String> = match read_dir(backup_root) {
+        Err(_) => Vec::new(),
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| path.file_stem().map(|name| name.to_string_lossy().to_string()))
+            .filter(|name| !name.ends_with(PARTIAL))
+            .collect()
+    };
+    // snapshots are named after their timestamp, so the lexicographic order is the chronological one
+    snapshots.sort();
+    snapshots
+}
+
+/// the most recent interrupted snapshot of a profile taken from the same sources, with the entries stored so far:
+/// either left in its staging files, or committed incomplete because it was cancelled
+pub fn resumable(backup_root: &Path, profile: &str, sources_checksum: &str) -> Option<Manifest> {
+    let staged = partials(backup_root).into_iter().filter_map(|snapshot| load_staged(backup_root, &snapshot).ok());
+    let cancelled = latest(backup_root, profile).filter(|manifest| manifest.incomplete);
+    staged.chain(cancelled)
+        .filter(|manifest| manifest.profile == profile && manifest.sources_checksum == sources_checksum)
+        .max_by(|a, b| a.snapshot.cmp(&b.snapshot))
+}
+
+/// the staged manifest of an interrupted snapshot, or the one recorded by its journal when it didn't get that far
+pub fn load_staged(backup_root: &Path, snapshot: &str) -> Result<Manifest, Box<dyn Error>> {
+    let staged = manifest_path(backup_root, &staging(snapshot));
+    if staged.is_file() {
+        return Manifest::load(&staged);
+    }
+    Journal::read(&journal_path(backup_root, snapshot))
+}
+
+/// append only record of a snapshot while it is written: the manifest without entries, then one stored entry per line
+/// the files are synced before they are recorded, so an interrupted backup can trust the entries and go on from there
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// starts the journal of a manifest, which has no entries yet
+    pub fn create(path: &Path, manifest: &Manifest) -> Result<Self, Box<dyn Error>> {
+        let mut journal = Self { file: File::create(path)? };
+        journal.write(&serde_json::to_string(manifest)?)?;
+        Ok(journal)
+    }
+
+    pub fn append(&mut self, entry: &ManifestEntry) -> Result<(), Box<dyn Error>> {
+        self.write(&serde_json::to_string(entry)?)
+    }
+
+    /// one write per line, so that an interruption can only tear the last one
+    fn write(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+        self.file.write_all(format!("{}\n", line).as_bytes())?;
+        Ok(())
+    }
+
+    /// the manifest with the entries recorded so far, a torn last line is left out
+    pub fn read(path: &Path) -> Result<Manifest, Box<dyn Error>> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header = lines.next().ok_or("empty journal")??;
+        let mut manifest: Manifest = serde_json::from_str(&header)?;
+        manifest.entries = lines
+            .map_while(Result::ok)
+            .map_while(|line| serde_json::from_str(&line).ok())
+            .collect();
+        Ok(manifest)
+    }
+}
+
+/// loads the manifest of the most recent snapshot of a profile in the backup root, if there is one
+pub fn latest(backup_root: &Path, profile: &str) -> Option<Manifest> {
+    snapshots(backup_root).iter().rev()
+        .filter_map(|snapshot| Manifest::load(&manifest_path(backup_root, snapshot)).ok())
+        .find(|manifest| manifest.profile == profile)
+}
+
+/// snapshots written before profiles existed belong to the default one
+fn default_profile() -> String {
+    DEFAULT_PROFILE.to_string()
+}
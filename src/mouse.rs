@@ -1,137 +1,86 @@
-use std::cmp::PartialEq;
-use std::thread;
-use std::time::Duration;
+use crate::config::Config;
+use crate::gesture::{Progress, Recognizer};
+use crate::input::InputEvent;
+use crate::screen::get_screen;
+use crate::trigger::{Phase, Signal, Trigger};
 
-use mouse_position::mouse_position::Mouse;
-use rdev::display_size;
-
-use crate::{audio, tokio};
-use crate::audio::{play_sound, SOUND_ARM, SOUND_CANCEL, SOUND_TRIGGER};
-use crate::backup::backup_main;
-use crate::logger::{error, info};
-use crate::state::{APP_STATE, ApplicationState};
-use crate::TOKIO;
-
-#[allow(dead_code)]
-#[derive(PartialEq)]
-enum Position {
-    TopLeft,
-    TopRight,
-    BottomRight,
-    BottomLeft,
-    Outside,
-    Inside,
-}
-struct SafeArea {
-    margin_left: u32,
-    margin_right: u32,
-    margin_top: u32,
-    margin_bottom: u32
+/// arms, confirms and aborts backups with mouse gestures
+pub struct MouseTrigger {
+    /// each bound gesture is tracked independently, the one that arms is the one that must confirm
+    gestures: Vec<(Recognizer, String)>,
+    armed: Option<usize>,
+    /// without a confirm gesture the arming one must be repeated
+    confirm: Option<Recognizer>,
+    abort: Option<Recognizer>,
 }
 
-fn get_safe_area() -> SafeArea {
-    let (width, height) = display_size().unwrap();
-    let (width, height) = (width as u32, height as u32);
-    let margin = width / 6;
-    SafeArea {
-        margin_top: margin,
-        margin_left: margin,
-        margin_bottom: height - margin,
-        margin_right: width - margin
+impl MouseTrigger {
+    pub fn new(config: &Config) -> Self {
+        let screen = get_screen(&config.gesture.monitor);
+        let recognizer = |gesture: &str| config.gesture_definition(gesture)
+            .map(|definition| Recognizer::new(&definition, config.gesture.margin, screen));
+        Self {
+            gestures: config.gestures().into_iter()
+                .filter_map(|(gesture, profile)| recognizer(&gesture).map(|recognizer| (recognizer, profile)))
+                .collect(),
+            armed: None,
+            confirm: config.gesture.confirm.as_deref().and_then(recognizer),
+            abort: config.gesture.abort.as_deref().and_then(recognizer),
+        }
     }
 }
 
-/// this thread controls the backup triggering, it listens for mouse movements and plays audio
-pub fn mouse_main() {
-    let safe_area = get_safe_area();
-    let mut position = Position::Outside;
-    let mut armed = false;
-
-    loop {
-        if APP_STATE.read() == ApplicationState::Quit {
-            break;
-        }
-        match Mouse::get_mouse_position() {
-            Mouse::Position { x, y } => {
-                let x = x as u32;
-                let y = y as u32;
+impl Trigger for MouseTrigger {
+    fn name(&self) -> &'static str {
+        "mouse"
+    }
 
-                if armed {
-                    // second path
-                    if path_completed(&mut position, &safe_area, x, y) {
-                        // trigger
-                        armed = false;
-                        tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_TRIGGER));
-                        info!("mouse", "Backup triggered!");
-                        // spawn backup thread
-                        // the mouse thread is blocked until the backup finishes, then it's ready to fire again
-                        thread::spawn(backup_main)
-                            .join()
-                            .inspect_err(|_e| { error!("mouse", "Backup thread panicked!"); })
-                            .err();
-                        info!("mouse", "Resumed probing mouse.");
-                    } else if position == Position::Outside {
-                        // fell out, disarm
-                        armed = false;
-                        tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_CANCEL));
-                        info!("mouse", "Backup disarmed.");
-                    }
-                } else {
-                    // first path
-                    if path_completed(&mut position, &safe_area, x, y) {
-                        // arm
-                        armed = true;
-                        tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_ARM));
-                        info!("mouse", "Backup armed");
+    fn feed(&mut self, event: &InputEvent, phase: Phase) -> Signal {
+        let InputEvent::MouseMove { x, y } = *event else { return Signal::None; };
+        match phase {
+            Phase::Idle => {
+                // first path
+                for (idx, (gesture, profile)) in self.gestures.iter_mut().enumerate() {
+                    if gesture.feed(x, y) == Progress::Completed {
+                        self.armed = Some(idx);
+                        return Signal::Arm(profile.clone());
                     }
                 }
+                Signal::None
             },
-            Mouse::Error => {
-                error!("mouse", "Error getting mouse position!");
-                break;
+            Phase::Armed => {
+                // second path
+                let progress = match (self.confirm.as_mut(), self.armed) {
+                    (Some(confirm), _) => confirm.feed(x, y),
+                    (None, Some(idx)) => self.gestures[idx].0.feed(x, y),
+                    (None, None) => Progress::None
+                };
+                match progress {
+                    Progress::Completed => Signal::Confirm,
+                    Progress::Broken => Signal::Disarm,
+                    Progress::None => Signal::None
+                }
             },
+            Phase::Running => {
+                if self.abort.as_mut().is_some_and(|abort| abort.feed(x, y) == Progress::Completed) {
+                    Signal::Abort
+                } else {
+                    Signal::None
+                }
+            },
+            Phase::Waiting | Phase::Paused => Signal::None
         }
-        // this is a low level loop, we have no other choice than to sleep the thread
-        // at least we can choose the update interval, and in this cas it can be very long
-        thread::sleep(Duration::from_millis(200));
-    }
-}
-
-/// returns true when the path is closed, then starts over again
-fn path_completed(position: &mut Position, safe_area: &SafeArea, x: u32, y: u32) -> bool {
-    if (x > safe_area.margin_left && x < safe_area.margin_right)
-        && (y > safe_area.margin_top && y < safe_area.margin_bottom) {
-        *position = Position::Outside;
-        return false;
     }
 
-    match position {
-        Position::Outside | Position::TopRight => {
-            if x < safe_area.margin_left && y < safe_area.margin_top {
-                *position = Position::TopLeft;
-            }
-        },
-        Position::TopLeft => {
-            if x < safe_area.margin_left && y > safe_area.margin_bottom {
-                *position = Position::BottomLeft;
-            }
-        },
-        Position::BottomLeft => {
-            if x > safe_area.margin_right && y > safe_area.margin_bottom {
-                *position = Position::BottomRight;
-            }
-        },
-        Position::BottomRight => {
-            if x > safe_area.margin_right && y < safe_area.margin_top {
-                *position = Position::TopRight;
-                return true;
-            }
-        },
-        Position::Inside => { *position = Position::Inside }
+    fn reset(&mut self) {
+        for (gesture, _) in self.gestures.iter_mut() {
+            gesture.reset();
+        }
+        if let Some(confirm) = self.confirm.as_mut() {
+            confirm.reset();
+        }
+        if let Some(abort) = self.abort.as_mut() {
+            abort.reset();
+        }
     }
-
-    return false;
 }
-
-
-
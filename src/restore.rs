@@ -0,0 +1,217 @@
+use std::error::Error;
+use std::fs::{create_dir_all, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use clap::{Args, ValueEnum};
+use glob::Pattern;
+use sysinfo::Disks;
+
+use crate::backup::{copy_hashed, HumanReadable};
+use crate::config;
+use crate::logger::{error, info};
+use crate::manifest;
+use crate::manifest::{Manifest, ManifestEntry};
+
+/// what to do when a restored file already exists
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum Conflict {
+    /// keep the existing file
+    Skip,
+    /// replace the existing file
+    Overwrite,
+    /// restore next to the existing file with a numbered name
+    Rename,
+}
+
+#[derive(Args)]
+pub struct RestoreArgs {
+    /// Snapshot to restore, the available snapshots are listed if missing
+    pub snapshot: Option<String>,
+    /// Mount point of the drive holding the snapshots, any mounted drive if missing
+    #[arg(long)]
+    pub drive: Option<PathBuf>,
+    /// Restore only the files whose original path matches the glob pattern, can be repeated
+    #[arg(long, value_name = "PATTERN")]
+    pub include: Vec<String>,
+    /// Restore into this directory instead of the original locations
+    #[arg(long)]
+    pub target: Option<PathBuf>,
+    /// What to do when a restored file already exists
+    #[arg(long, value_enum, default_value_t = Conflict::Skip)]
+    pub conflict: Conflict,
+    /// Only print what would be restored
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    /// Mount point of the drive holding the snapshots, any mounted drive if missing
+    #[arg(long)]
+    pub drive: Option<PathBuf>,
+    /// List only the snapshots of this profile
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+/// the mounted drives, or the given one
+pub fn mount_points(drive: Option<&Path>) -> Vec<PathBuf> {
+    match drive {
+        Some(drive) => vec![drive.to_path_buf()],
+        None => {
+            let disks = Disks::new_with_refreshed_list();
+            disks.list().into_iter().map(|disk| disk.mount_point().to_path_buf()).collect()
+        }
+    }
+}
+
+/// backup folders of all the profiles in the mounted drives (or in the given one) that contain snapshots
+pub fn backup_roots(drive: Option<&Path>) -> Vec<PathBuf> {
+    let mounts = mount_points(drive);
+    let folders = config::get().destination_folders();
+    mounts.iter()
+        .flat_map(|mount| folders.iter().map(|folder| mount.join(folder)))
+        .filter(|backup_root| backup_root.is_dir())
+        .collect()
+}
+
+pub fn restore_main(options: RestoreArgs) -> Result<(), Box<dyn Error>> {
+    let backup_roots = backup_roots(options.drive.as_deref());
+    if backup_roots.is_empty() {
+        return Err("No mounted drive contains snapshots!".into());
+    }
+
+    let snapshot = match &options.snapshot {
+        None => {
+            list_snapshots(&backup_roots, None);
+            return Ok(());
+        },
+        Some(snapshot) => snapshot
+    };
+
+    let backup_root = backup_roots.iter()
+        .find(|backup_root| manifest::manifest_path(backup_root, snapshot).is_file())
+        .ok_or(format!("Snapshot not found: {}", snapshot))?;
+    let manifest = Manifest::load(&manifest::manifest_path(backup_root, snapshot))?;
+
+    let patterns = options.include.iter()
+        .map(|pattern| Pattern::new(pattern))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    info!("restore", format!("Restoring snapshot {} from {}", snapshot, backup_root.display()));
+
+    let mut restored = 0;
+    let mut skipped = 0;
+    let mut failed = 0;
+
+    for entry in manifest.entries.iter().filter(|entry| entry.is_stored()) {
+        if !patterns.is_empty() && !patterns.iter().any(|pattern| pattern.matches_path(&entry.source)) {
+            continue;
+        }
+
+        let from = backup_root.join(entry.holder(snapshot)).join(&entry.path);
+        let mut to = match &options.target {
+            Some(target) => target.join(&entry.path),
+            None => entry.source.clone()
+        };
+
+        if to.exists() {
+            match options.conflict {
+                Conflict::Skip => {
+                    println!("skip      {}", to.display());
+                    skipped += 1;
+                    continue;
+                },
+                Conflict::Overwrite => {},
+                Conflict::Rename => { to = free_path(&to); }
+            }
+        }
+
+        println!("{:<9} {} -> {}", if options.dry_run { "would" } else { "restore" }, from.display(), to.display());
+
+        if options.dry_run {
+            restored += 1;
+            continue;
+        }
+
+        match restore_file(entry, &from, &to) {
+            Ok(_) => { restored += 1; },
+            Err(e) => {
+                let msg = format!("Error restoring {}: {}", to.display(), e);
+                error!("restore", msg.clone());
+                eprintln!("{}", msg);
+                failed += 1;
+            }
+        }
+    }
+
+    let msg = format!("Restored: {}, skipped: {}, failed: {}", restored, skipped, failed);
+    info!("restore", msg.clone());
+    println!("\n{}", msg);
+
+    if failed > 0 {
+        return Err(format!("{} files could not be restored!", failed).into());
+    }
+    Ok(())
+}
+
+pub fn list_main(options: ListArgs) -> Result<(), Box<dyn Error>> {
+    let backup_roots = backup_roots(options.drive.as_deref());
+    if backup_roots.is_empty() {
+        return Err("No mounted drive contains snapshots!".into());
+    }
+    list_snapshots(&backup_roots, options.profile.as_deref());
+    Ok(())
+}
+
+fn list_snapshots(backup_roots: &Vec<PathBuf>, profile: Option<&str>) {
+    for backup_root in backup_roots {
+        println!("{}", backup_root.display());
+        for snapshot in manifest::snapshots(backup_root) {
+            match Manifest::load(&manifest::manifest_path(backup_root, &snapshot)) {
+                Ok(manifest) if profile.is_some_and(|profile| profile != manifest.profile) => {},
+                Ok(manifest) => {
+                    let size: u64 = manifest.entries.iter().map(|entry| entry.size).sum();
+                    println!("  {:<32} {:>8} files {:>12}   {} @ {} ({}){}",
+                             snapshot, manifest.entries.len(), size.human_readable(), manifest.started, manifest.host, manifest.profile,
+                             if manifest.incomplete { " incomplete" } else { "" });
+                },
+                Err(e) => {
+                    println!("  {:<32} unreadable manifest: {}", snapshot, e);
+                }
+            }
+        }
+        for partial in manifest::partials(backup_root) {
+            println!("  {:<32} interrupted, never completed", manifest::staging(&partial));
+        }
+    }
+}
+
+/// copies a file out of the snapshot, restoring its modification time and permissions
+fn restore_file(entry: &ManifestEntry, from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = to.parent() {
+        create_dir_all(parent)?;
+    }
+    let hash = copy_hashed(from, to)?;
+    if entry.hash.as_ref() != Some(&hash) {
+        return Err("content does not match the hash in the manifest".into());
+    }
+    File::options().write(true).open(to)?.set_modified(UNIX_EPOCH + Duration::from_secs(entry.modified))?;
+    entry.permissions.apply(to)?;
+    Ok(())
+}
+
+/// first non existing path obtained by numbering the file name, e.g. `file (1).txt`
+fn free_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let extension = path.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    let mut n = 1;
+    loop {
+        let candidate = path.with_file_name(format!("{} ({}){}", stem, n, extension));
+        if !candidate.exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
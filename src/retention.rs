@@ -0,0 +1,384 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{remove_dir_all, remove_file};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, Local};
+use clap::Args;
+use walkdir::WalkDir;
+
+use crate::backup::{backup_root, HumanReadable};
+use crate::config;
+use crate::config::RetentionConfig;
+use crate::control;
+use crate::control::Request;
+use crate::logger::{error, info};
+use crate::manifest;
+use crate::manifest::{Manifest, Outcome, Permissions};
+use crate::restore::mount_points;
+
+#[derive(Args)]
+pub struct PruneArgs {
+    /// Mount point of the drive holding the snapshots, any mounted drive if missing
+    #[arg(long)]
+    pub drive: Option<PathBuf>,
+    /// Prune only the snapshots of this profile
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// Only print what would be deleted
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// the period a snapshot time falls in, e.g. (year, day of the year)
+type Bucket = fn(&DateTime<Local>) -> (i32, u32);
+
+/// what the retention decided for a snapshot
+pub struct Decision {
+    pub snapshot: String,
+    /// why the snapshot is kept, none if it's deleted
+    pub keep: Option<String>,
+}
+
+/// decides which snapshots of a profile are kept, the manifests are sorted newest first
+/// the latest complete snapshot is always kept, and so are the snapshots holding content that a kept one refers to;
+/// incomplete (cancelled) snapshots don't count for the rules, only the newest snapshot is kept when incomplete,
+/// since the next backup resumes it, and the staged manifests of the interrupted backups keep what they refer to
+pub fn plan(manifests: &[Manifest], staged: &[Manifest], retention: &RetentionConfig) -> Vec<Decision> {
+    let mut keep: Vec<Option<String>> = vec![None; manifests.len()];
+    if !retention.counted() {
+        keep.fill(Some("within the size limit".to_string()));
+    }
+    let complete: Vec<usize> = (0..manifests.len()).filter(|idx| !manifests[*idx].incomplete).collect();
+    // never deleted, whatever the size
+    let mut protected = Vec::new();
+    if let Some(&latest) = complete.first() {
+        keep[latest] = Some("latest complete".to_string());
+        protected.push(latest);
+    }
+    if manifests.first().is_some_and(|manifest| manifest.incomplete) {
+        keep[0] = Some("incomplete, resumed by the next backup".to_string());
+        protected.push(0);
+    }
+
+    if let Some(last) = retention.keep_last {
+        for idx in complete.iter().take(last) {
+            keep[*idx].get_or_insert(format!("last {}", last));
+        }
+    }
+
+    // the newest snapshot of each bucket, for as many buckets as asked
+    let started: Vec<Option<DateTime<Local>>> = manifests.iter()
+        .map(|manifest| DateTime::parse_from_rfc3339(&manifest.started).ok().map(|time| time.with_timezone(&Local)))
+        .collect();
+    let buckets: [(Option<usize>, &str, Bucket); 3] = [
+        (retention.keep_daily, "daily", |time| (time.year(), time.ordinal())),
+        (retention.keep_weekly, "weekly", |time| (time.iso_week().year(), time.iso_week().week())),
+        (retention.keep_monthly, "monthly", |time| (time.year(), time.month())),
+    ];
+    for (count, name, bucket) in buckets {
+        let Some(count) = count else { continue; };
+        let mut seen = HashSet::new();
+        for idx in complete.iter().copied() {
+            let Some(time) = &started[idx] else { continue; };
+            let bucket = bucket(time);
+            if seen.contains(&bucket) {
+                continue;
+            }
+            if seen.len() == count {
+                break;
+            }
+            seen.insert(bucket);
+            keep[idx].get_or_insert(name.to_string());
+        }
+    }
+
+    // from the newest, the kept snapshots go once their content doesn't fit anymore
+    if let Some(max_total_size) = retention.max_total_size {
+        let mut contents = HashSet::new();
+        let mut total_size = 0;
+        let mut full = false;
+        for (idx, manifest) in manifests.iter().enumerate() {
+            if keep[idx].is_none() {
+                continue;
+            }
+            let added: HashMap<&String, u64> = manifest.entries.iter()
+                .filter(|entry| entry.is_stored())
+                .filter_map(|entry| entry.hash.as_ref().map(|hash| (hash, entry.size)))
+                .filter(|(hash, _)| !contents.contains(*hash))
+                .collect();
+            let added_size: u64 = added.values().sum();
+            if !protected.contains(&idx) && (full || total_size + added_size > max_total_size) {
+                full = true;
+                keep[idx] = None;
+                continue;
+            }
+            total_size += added_size;
+            contents.extend(added.into_keys());
+        }
+    }
+
+    // a deleted snapshot would take away the content of the kept ones that refer to it,
+    // and of the interrupted ones that are committed as they are when resumed
+    loop {
+        let referenced: HashSet<&str> = manifests.iter().zip(keep.iter())
+            .filter(|(_, reason)| reason.is_some())
+            .map(|(manifest, _)| manifest)
+            .chain(staged.iter())
+            .flat_map(|manifest| manifest.entries.iter())
+            .filter_map(|entry| match &entry.outcome {
+                Outcome::Referenced { snapshot } => Some(snapshot.as_str()),
+                _ => None
+            })
+            .collect();
+        let mut changed = false;
+        for (manifest, reason) in manifests.iter().zip(keep.iter_mut()) {
+            if reason.is_none() && referenced.contains(manifest.snapshot.as_str()) {
+                *reason = Some("referenced by a kept or interrupted snapshot".to_string());
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    manifests.iter().zip(keep)
+        .map(|(manifest, keep)| Decision { snapshot: manifest.snapshot.clone(), keep })
+        .collect()
+}
+
+/// applies the retention of a profile to its snapshots in a backup folder, the oldest are deleted first
+/// returns the decisions, newest first, and the size of the content that only the deleted snapshots hold
+/// snapshots with unreadable manifests and the ones being written are never touched
+pub fn prune(backup_root: &Path, profile: &str, dry_run: bool) -> Result<(Vec<Decision>, u64), Box<dyn Error>> {
+    let config = config::get();
+    let retention = &config.destination(profile).retention;
+    let manifests: Vec<Manifest> = manifest::snapshots(backup_root).iter().rev()
+        .filter_map(|snapshot| Manifest::load(&manifest::manifest_path(backup_root, snapshot)).ok())
+        .filter(|manifest| manifest.profile == profile)
+        .collect();
+    // the interrupted backups of any profile, whether they are resumed or not
+    let staged: Vec<Manifest> = manifest::partials(backup_root).iter()
+        .filter_map(|snapshot| manifest::load_staged(backup_root, snapshot).ok())
+        .collect();
+    let decisions = plan(&manifests, &staged, retention);
+
+    let kept: HashSet<&String> = manifests.iter().zip(decisions.iter())
+        .filter(|(_, decision)| decision.keep.is_some())
+        .flat_map(|(manifest, _)| manifest.entries.iter().filter_map(|entry| entry.hash.as_ref()))
+        .collect();
+    let freed: u64 = manifests.iter().zip(decisions.iter())
+        .filter(|(_, decision)| decision.keep.is_none())
+        .flat_map(|(manifest, _)| manifest.entries.iter().filter(|entry| entry.is_stored()))
+        .filter_map(|entry| entry.hash.as_ref().map(|hash| (hash, entry.size)))
+        .filter(|(hash, _)| !kept.contains(hash))
+        .collect::<HashMap<&String, u64>>()
+        .values()
+        .sum();
+
+    if dry_run {
+        return Ok((decisions, freed));
+    }
+
+    let mut failed = 0;
+    for decision in decisions.iter().rev().filter(|decision| decision.keep.is_none()) {
+        match delete(backup_root, &decision.snapshot) {
+            Ok(_) => { info!("retention", format!("Snapshot {} deleted from {}.", decision.snapshot, backup_root.display())); },
+            Err(e) => {
+                error!("retention", format!("Cannot delete snapshot {} from {}! {}", decision.snapshot, backup_root.display(), e));
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} snapshots could not be deleted!", failed).into());
+    }
+    Ok((decisions, freed))
+}
+
+/// deletes a snapshot, the manifest first so that a half deleted snapshot is never listed
+fn delete(backup_root: &Path, snapshot: &str) -> io::Result<()> {
+    remove_file(manifest::manifest_path(backup_root, snapshot))?;
+    let folder = backup_root.join(snapshot);
+    if folder.is_dir() && remove_dir_all(&folder).is_err() {
+        // read only files can't be deleted on windows
+        for entry in WalkDir::new(&folder).into_iter().filter_map(Result::ok) {
+            Permissions { readonly: false, mode: None }.apply(entry.path()).ok();
+        }
+        remove_dir_all(&folder)?;
+    }
+    let log = manifest::log_path(backup_root, snapshot);
+    if log.is_file() {
+        remove_file(log)?;
+    }
+    Ok(())
+}
+
+/// prunes the snapshots of the profiles that have a retention, on the mounted drives or on the given one
+pub fn prune_main(args: PruneArgs) -> Result<(), Box<dyn Error>> {
+    let config = config::get();
+    let profiles = match &args.profile {
+        Some(profile) if !config.has_profile(profile) => { return Err(format!("Unknown profile: {}", profile).into()); },
+        Some(profile) => vec![profile.clone()],
+        None => config.profile_names()
+    };
+
+    // the running backup links and refers to the latest snapshots
+    if !args.dry_run {
+        if let Ok(response) = control::send(&Request::Status) {
+            if response.status.is_some_and(|status| status.state.is_backup()) {
+                return Err("A backup is running, prune once it's over.".into());
+            }
+        }
+    }
+
+    let mounts = mount_points(args.drive.as_deref());
+    let mut failed = 0;
+    for profile in profiles {
+        let retention = &config.destination(&profile).retention;
+        if !retention.is_set() {
+            println!("Profile {} has no retention, all its snapshots are kept.", profile);
+            continue;
+        }
+        for mount in mounts.iter() {
+            let backup_root = backup_root(mount, &profile);
+            if !backup_root.is_dir() {
+                continue;
+            }
+            println!("{} ({}: {})", backup_root.display(), profile, retention.describe());
+            let (decisions, freed) = match prune(&backup_root, &profile, args.dry_run) {
+                Ok(pruned) => pruned,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            for decision in decisions.iter() {
+                match &decision.keep {
+                    Some(reason) => println!("  keep    {:<32} {}", decision.snapshot, reason),
+                    None => println!("  {:<7} {}", if args.dry_run { "would" } else { "delete" }, decision.snapshot)
+                }
+            }
+            let deleted = decisions.iter().filter(|decision| decision.keep.is_none()).count();
+            println!("{} {} snapshots, freeing about {}.\n", if args.dry_run { "Would delete" } else { "Deleted" }, deleted, freed.human_readable());
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("Pruning failed on {} drives!", failed).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::config::RetentionConfig;
+    use crate::manifest::{Manifest, ManifestEntry, Outcome, Permissions};
+
+    use super::plan;
+
+    /// a snapshot holding (hash, size) contents, the ones with a snapshot name are referenced from it
+    fn snapshot(name: &str, started: &str, incomplete: bool, contents: &[(&str, u64, Option<&str>)]) -> Manifest {
+        let mut manifest = Manifest::new(name.to_string(), "default".to_string(), "checksum".to_string());
+        manifest.started = started.to_string();
+        manifest.incomplete = incomplete;
+        manifest.entries = contents.iter().map(|(hash, size, referenced)| ManifestEntry {
+            path: PathBuf::from(hash),
+            source: PathBuf::from(hash),
+            size: *size,
+            modified: 0,
+            permissions: Permissions { readonly: false, mode: None },
+            hash: Some(hash.to_string()),
+            outcome: match referenced {
+                Some(snapshot) => Outcome::Referenced { snapshot: snapshot.to_string() },
+                None => Outcome::Copied
+            },
+            verified: None
+        }).collect();
+        manifest
+    }
+
+    /// the snapshots that are kept
+    fn kept(manifests: &[Manifest], retention: &RetentionConfig) -> Vec<String> {
+        plan(manifests, &[], retention).into_iter().filter(|decision| decision.keep.is_some()).map(|decision| decision.snapshot).collect()
+    }
+
+    #[test]
+    fn keep_last() {
+        let manifests: Vec<Manifest> = (0..5).rev().map(|n| snapshot(&format!("s{}", n), "2026-01-01T12:00:00+00:00", false, &[])).collect();
+        let retention = RetentionConfig { keep_last: Some(2), ..Default::default() };
+        assert_eq!(kept(&manifests, &retention), ["s4", "s3"]);
+    }
+
+    #[test]
+    fn daily_buckets_keep_the_newest_of_each_day() {
+        let manifests = [
+            snapshot("s4", "2026-01-03T12:30:00+00:00", false, &[]),
+            snapshot("s3", "2026-01-03T12:00:00+00:00", false, &[]),
+            snapshot("s2", "2026-01-02T12:00:00+00:00", false, &[]),
+            snapshot("s1", "2026-01-01T12:00:00+00:00", false, &[]),
+        ];
+        let retention = RetentionConfig { keep_daily: Some(2), ..Default::default() };
+        assert_eq!(kept(&manifests, &retention), ["s4", "s2"]);
+    }
+
+    #[test]
+    fn max_total_size_counts_each_content_once() {
+        let manifests = [
+            snapshot("s3", "2026-01-03T12:00:00+00:00", false, &[("a", 10, None), ("b", 10, None)]),
+            snapshot("s2", "2026-01-02T12:00:00+00:00", false, &[("a", 10, None)]),
+            snapshot("s1", "2026-01-01T12:00:00+00:00", false, &[("c", 10, None)]),
+        ];
+        let retention = RetentionConfig { max_total_size: Some(25), ..Default::default() };
+        assert_eq!(kept(&manifests, &retention), ["s3", "s2"]);
+    }
+
+    #[test]
+    fn referenced_snapshots_are_kept() {
+        let manifests = [
+            snapshot("s3", "2026-01-03T12:00:00+00:00", false, &[("a", 10, Some("s1"))]),
+            snapshot("s2", "2026-01-02T12:00:00+00:00", false, &[]),
+            snapshot("s1", "2026-01-01T12:00:00+00:00", false, &[("a", 10, None)]),
+        ];
+        let retention = RetentionConfig { keep_last: Some(1), ..Default::default() };
+        assert_eq!(kept(&manifests, &retention), ["s3", "s1"]);
+    }
+
+    #[test]
+    fn snapshots_referenced_by_an_interrupted_backup_are_kept() {
+        let manifests = [
+            snapshot("s3", "2026-01-03T12:00:00+00:00", false, &[]),
+            snapshot("s2", "2026-01-02T12:00:00+00:00", false, &[("b", 10, None)]),
+            snapshot("s1", "2026-01-01T12:00:00+00:00", false, &[("a", 10, None)]),
+        ];
+        let staged = [snapshot("s4", "2026-01-04T12:00:00+00:00", false, &[("a", 10, Some("s1"))])];
+        let retention = RetentionConfig { keep_last: Some(1), ..Default::default() };
+        let kept: Vec<String> = plan(&manifests, &staged, &retention).into_iter()
+            .filter(|decision| decision.keep.is_some())
+            .map(|decision| decision.snapshot)
+            .collect();
+        assert_eq!(kept, ["s3", "s1"]);
+    }
+
+    #[test]
+    fn incomplete_snapshots_dont_count() {
+        let manifests = [
+            snapshot("s4", "2026-01-04T12:00:00+00:00", true, &[]),
+            snapshot("s3", "2026-01-03T12:00:00+00:00", false, &[("b", 10, None)]),
+            snapshot("s2", "2026-01-02T12:00:00+00:00", true, &[]),
+            snapshot("s1", "2026-01-01T12:00:00+00:00", false, &[("c", 10, None)]),
+        ];
+        // the newest one is resumed by the next backup, the latest complete one is kept anyway
+        let retention = RetentionConfig { keep_last: Some(1), ..Default::default() };
+        assert_eq!(kept(&manifests, &retention), ["s4", "s3"]);
+        let retention = RetentionConfig { keep_last: Some(2), max_total_size: Some(15), ..Default::default() };
+        assert_eq!(kept(&manifests, &retention), ["s4", "s3"]);
+        let retention = RetentionConfig { keep_last: Some(2), ..Default::default() };
+        assert_eq!(kept(&manifests, &retention), ["s4", "s3", "s1"]);
+    }
+}
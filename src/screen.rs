@@ -0,0 +1,95 @@
+use display_info::DisplayInfo;
+use rdev::display_size;
+use serde::Deserialize;
+
+use crate::logger::{error, info};
+
+/// the monitor the gestures are drawn on
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[serde(untagged)]
+pub enum Monitor {
+    /// position in the list of the displays logged at startup, starting from 0
+    Index(usize),
+    /// "primary", or "virtual" for the bounding box of all the displays
+    Name(String),
+}
+
+/// a rectangle of the virtual desktop, the origin is the top left corner of the primary display
+/// so the displays on its left or above it have negative coordinates
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Screen {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Monitor::Name("primary".to_string())
+    }
+}
+
+impl Monitor {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Monitor::Name(name) if name != "primary" && name != "virtual" => Err(format!("monitor must be \"primary\", \"virtual\" or the index of a display, found \"{}\"", name)),
+            _ => Ok(())
+        }
+    }
+}
+
+impl Screen {
+    /// smallest rectangle containing all the screens
+    fn bounding_box(screens: &[Screen]) -> Option<Screen> {
+        let left = screens.iter().map(|screen| screen.x as i64).min()?;
+        let top = screens.iter().map(|screen| screen.y as i64).min()?;
+        let right = screens.iter().map(|screen| screen.x as i64 + screen.width as i64).max()?;
+        let bottom = screens.iter().map(|screen| screen.y as i64 + screen.height as i64).max()?;
+        Some(Screen {
+            x: left as i32,
+            y: top as i32,
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+        })
+    }
+}
+
+/// area of the chosen monitor, the primary display if it does not exist
+pub fn get_screen(monitor: &Monitor) -> Screen {
+    let displays = match DisplayInfo::all() {
+        Ok(displays) => displays,
+        Err(e) => {
+            error!("screen", format!("Cannot enumerate the displays! {}", e));
+            Vec::new()
+        }
+    };
+    for (idx, display) in displays.iter().enumerate() {
+        info!("screen", format!("Display {}: {}x{} at ({}, {}){}", idx, display.width, display.height, display.x, display.y,
+            if display.is_primary { ", primary" } else { "" }));
+    }
+
+    let screens: Vec<Screen> = displays.iter()
+        .map(|display| Screen { x: display.x, y: display.y, width: display.width, height: display.height })
+        .collect();
+    let primary = displays.iter().position(|display| display.is_primary)
+        .map(|idx| screens[idx])
+        .unwrap_or_else(primary_fallback);
+
+    let screen = match monitor {
+        Monitor::Index(idx) => screens.get(*idx).copied().unwrap_or_else(|| {
+            error!("screen", format!("Display {} not found, using the primary one.", idx));
+            primary
+        }),
+        Monitor::Name(name) if name == "virtual" => Screen::bounding_box(&screens).unwrap_or(primary),
+        Monitor::Name(_) => primary
+    };
+    info!("screen", format!("Gestures are drawn on {}x{} at ({}, {}).", screen.width, screen.height, screen.x, screen.y));
+    screen
+}
+
+/// the primary display at the origin, when the displays can't be enumerated
+fn primary_fallback() -> Screen {
+    let (width, height) = display_size().unwrap();
+    Screen { x: 0, y: 0, width: width as u32, height: height as u32 }
+}
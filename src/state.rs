@@ -1,27 +1,119 @@
+use std::fmt;
+use std::mem::discriminant;
+use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use tokio::sync::futures::Notified;
 use tokio::sync::Notify;
 
+use crate::backup::BackupOutcome;
+use crate::logger::info;
+
 lazy_static! {
     pub static ref APP_STATE : Arc<ApplicationStateManager> = ApplicationStateManager::new();
 }
 
-#[derive(PartialEq, Clone, Copy)]
+/// where the running backup is
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+pub struct BackupProgress {
+    pub profile: String,
+    /// files done so far, whatever their outcome
+    pub files: usize,
+    /// files to do, 0 while the sources are parsed
+    pub total_files: usize,
+    pub size: u64,
+    pub total_size: u64,
+    /// file being copied or verified
+    pub current: Option<PathBuf>,
+}
+
+/// how a backup ended
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct BackupResult {
+    pub profile: String,
+    /// none if the backup failed before creating it
+    pub snapshot: Option<String>,
+    pub outcome: BackupOutcome,
+    pub message: String,
+    /// rfc3339 time
+    pub finished: String,
+    /// the log written next to the snapshot
+    pub log: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(tag = "phase", rename_all = "snake_case")]
 pub enum ApplicationState {
-    Running,
+    /// waiting for a trigger
+    Idle,
+    /// the triggers are ignored, backups can still be started from the tray and the command line
+    Paused,
+    /// a trigger armed the backup of a profile and waits for the confirmation
+    Armed { profile: String, trigger: String },
+    /// copying the files
+    BackingUp(BackupProgress),
+    /// reading back the copied files
+    Verifying(BackupProgress),
+    /// the last backup ended, otherwise the same as idle
+    Finished(BackupResult),
     Quit
 }
 
-/// used to direct other threads flow without channels
+impl fmt::Display for ApplicationState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplicationState::Idle => write!(f, "idle"),
+            ApplicationState::Paused => write!(f, "paused"),
+            ApplicationState::Armed { profile, trigger } => write!(f, "armed ({} by {})", profile, trigger),
+            ApplicationState::BackingUp(progress) => write!(f, "backing up ({})", progress.profile),
+            ApplicationState::Verifying(progress) => write!(f, "verifying ({})", progress.profile),
+            ApplicationState::Finished(result) => write!(f, "finished ({}, {:?})", result.profile, result.outcome),
+            ApplicationState::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+impl ApplicationState {
+    /// a backup is running
+    pub fn is_backup(&self) -> bool {
+        matches!(self, ApplicationState::BackingUp(_) | ApplicationState::Verifying(_))
+    }
+
+    /// the transitions of the lifecycle, quitting is always possible and final
+    /// a backup can only start when none is running, which makes the state the lock of the backups
+    fn allows(&self, next: &ApplicationState) -> bool {
+        use ApplicationState::*;
+        match (self, next) {
+            (Quit, _) => false,
+            (_, Quit) => true,
+            (Idle | Finished(_), Armed { .. } | Paused | BackingUp(_)) => true,
+            (Paused, Idle | BackingUp(_)) => true,
+            (Armed { .. }, Idle | Paused | BackingUp(_)) => true,
+            (BackingUp(_), Verifying(_) | Finished(_)) => true,
+            (Verifying(_), Finished(_)) => true,
+            _ => false
+        }
+    }
+}
+
+struct Inner {
+    state: ApplicationState,
+    /// the backup was started while paused, it goes back to paused when it ends
+    paused: bool,
+    last_result: Option<BackupResult>,
+}
+
+/// used to direct other threads flow without channels, and the single source of truth about what the application is doing
 /// threads that do not go to sleep can actively read the state and take action
 /// threads that want to wait for changes without wasting cpu cycles can do so with the notify (wait channel)
-/// a thread can also subscribe to the changes and be notified via a priority channel, either sync or async
+/// a thread can also subscribe to the changes and be notified via a channel, either sync or async
+/// the channels are unbounded so that changing the state never blocks, even from a subscriber
 pub struct ApplicationStateManager {
-    state: RwLock<ApplicationState>,
+    inner: RwLock<Inner>,
     notify: Notify,
-    subscribers: RwLock<Vec<tokio::sync::mpsc::Sender<ApplicationState>>>,
+    subscribers: RwLock<Vec<tokio::sync::mpsc::UnboundedSender<ApplicationState>>>,
     subscribers_sync: RwLock<Vec<crossbeam::channel::Sender<ApplicationState>>>
 }
 
@@ -29,7 +121,7 @@ impl ApplicationStateManager {
     fn new() -> Arc<Self> {
         Arc::new(
             ApplicationStateManager {
-                state: RwLock::new(ApplicationState::Running),
+                inner: RwLock::new(Inner { state: ApplicationState::Idle, paused: false, last_result: None }),
                 notify: Notify::new(),
                 subscribers: RwLock::new(Vec::new()),
                 subscribers_sync: RwLock::new(Vec::new())
@@ -37,44 +129,73 @@ impl ApplicationStateManager {
     }
 
     pub fn read(&self) -> ApplicationState {
-        *(self.state.read().unwrap())
+        self.inner.read().unwrap().state.clone()
     }
-    pub async fn change(&self, state: ApplicationState) {
-        *self.state.write().unwrap() = state;
-        self.notify.notify_waiters();
-        let mut to_remove = Vec::new();
-        for (idx, tx) in self.subscribers.read().unwrap().iter().enumerate() {
-            if tx.send(state).await.is_err() {
-                to_remove.push(idx);
-            }
+
+    /// how the last backup ended, it outlives the finished state
+    pub fn last_result(&self) -> Option<BackupResult> {
+        self.inner.read().unwrap().last_result.clone()
+    }
+
+    /// moves to another phase, refused if the lifecycle doesn't allow it
+    pub fn change(&self, state: ApplicationState) -> Result<(), String> {
+        let mut inner = self.inner.write().unwrap();
+        if !inner.state.allows(&state) {
+            return Err(format!("cannot go from {} to {}", inner.state, state));
         }
-        for idx in to_remove.iter() {
-            self.subscribers.write().unwrap().remove(*idx);
+        info!("state", format!("{} -> {}", inner.state, state));
+        if state.is_backup() {
+            inner.paused = inner.state == ApplicationState::Paused;
         }
-        to_remove.clear();
-        for (idx, tx) in self.subscribers_sync.read().unwrap().iter().enumerate() {
-            if tx.send(state).is_err() {
-                to_remove.push(idx);
-            }
+        inner.state = state;
+        self.publish(&inner.state);
+        Ok(())
+    }
+
+    /// updates the payload of the current phase (e.g. the backup progress), ignored if the phase changed meanwhile
+    pub fn update(&self, state: ApplicationState) {
+        let mut inner = self.inner.write().unwrap();
+        if discriminant(&inner.state) == discriminant(&state) {
+            inner.state = state;
+            self.publish(&inner.state);
         }
-        for idx in to_remove.into_iter() {
-            self.subscribers_sync.write().unwrap().remove(idx);
+    }
+
+    /// ends the running backup, in the finished phase or back in the paused one
+    pub fn finish(&self, result: BackupResult) {
+        let mut inner = self.inner.write().unwrap();
+        inner.last_result = Some(result.clone());
+        if !inner.state.is_backup() {
+            return; // quitting
         }
+        let state = if inner.paused { ApplicationState::Paused } else { ApplicationState::Finished(result) };
+        info!("state", format!("{} -> {}", inner.state, state));
+        inner.paused = false;
+        inner.state = state;
+        self.publish(&inner.state);
     }
+
+    /// sends the state to the waiters and the subscribers, the ones that dropped their receiver are removed
+    fn publish(&self, state: &ApplicationState) {
+        self.notify.notify_waiters();
+        self.subscribers.write().unwrap().retain(|tx| tx.send(state.clone()).is_ok());
+        self.subscribers_sync.write().unwrap().retain(|tx| tx.send(state.clone()).is_ok());
+    }
+
     #[allow(dead_code)]
     pub fn wait_for_change(&self) -> Notified<'_> {
         self.notify.notified()
     }
 
-    pub fn subscribe(&self) -> tokio::sync::mpsc::Receiver<ApplicationState> {
-        let (tx, rx) = tokio::sync::mpsc::channel(1);
+    pub fn subscribe(&self) -> tokio::sync::mpsc::UnboundedReceiver<ApplicationState> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
         self.subscribers.write().unwrap().push(tx);
         rx
     }
 
     pub fn subscribe_sync(&self) -> crossbeam::channel::Receiver<ApplicationState> {
-        let (tx, rx) = crossbeam::channel::bounded(1);
+        let (tx, rx) = crossbeam::channel::unbounded();
         self.subscribers_sync.write().unwrap().push(tx);
         rx
     }
-}
\ No newline at end of file
+}
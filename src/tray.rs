@@ -1,15 +1,63 @@
+use std::io;
+use std::path::{absolute, Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+use chrono::DateTime;
 use crossbeam::channel::bounded;
 use crossbeam::select;
+use native_dialog::{MessageDialog, MessageType};
 use tray_item::{IconSource, TrayItem};
 
-use crate::{TOKIO, tokio};
-use crate::logger::info;
+use crate::config;
+use crate::backup::{backup_main, BackupOutcome};
+use crate::logger::{error, info};
 use crate::state::{APP_STATE, ApplicationState};
 
+/// the process log, written by the logger
+const LOG_FILE: &str = "blackout.log";
+
 enum Action {
+    Backup(String),
+    /// pause or resume the gestures and hotkeys
+    TogglePause,
+    ShowReport,
+    OpenLog,
+    OpenConfig,
     Quit,
 }
 
+/// a menu entry whose label follows the application state
+/// the macos backend can't change the labels, there the entry keeps a label that fits any state
+struct Entry {
+    #[cfg(any(windows, target_os = "linux"))]
+    id: u32,
+    label: String,
+}
+
+impl Entry {
+    #[cfg(any(windows, target_os = "linux"))]
+    fn new(tray: &mut TrayItem, label: String, _fixed: &str, cb: impl Fn() + Send + Sync + 'static) -> Self {
+        let id = tray.inner_mut().add_menu_item_with_id(&label, cb).unwrap();
+        Self { id, label }
+    }
+
+    #[cfg(not(any(windows, target_os = "linux")))]
+    fn new(tray: &mut TrayItem, _label: String, fixed: &str, cb: impl Fn() + Send + Sync + 'static) -> Self {
+        tray.add_menu_item(fixed, cb).unwrap();
+        Self { label: fixed.to_string() }
+    }
+
+    #[allow(unused_variables)]
+    fn set(&mut self, tray: &mut TrayItem, label: String) {
+        #[cfg(any(windows, target_os = "linux"))]
+        if label != self.label {
+            tray.inner_mut().set_menu_item_label(&label, self.id).ok();
+            self.label = label;
+        }
+    }
+}
+
 pub fn tray_main() {
     // define tray
     let mut tray = TrayItem::new(
@@ -21,6 +69,35 @@ pub fn tray_main() {
     let (tx, rx) = bounded(1);
 
     // event senders
+
+    let mut status = Entry::new(&mut tray, status_line(&APP_STATE.read()), "Blackout", || {});
+
+    let profiles = config::get().profile_names();
+    for profile in profiles.iter().cloned() {
+        let backup_tx = tx.clone();
+        let label = if profiles.len() == 1 { "Backup now".to_string() } else { format!("Backup now: {}", profile) };
+        tray.add_menu_item(&label, move || {
+            backup_tx.send(Action::Backup(profile.clone())).unwrap();
+        }).unwrap();
+    }
+
+    let pause_tx = tx.clone();
+    let mut pause = Entry::new(&mut tray, pause_label(&APP_STATE.read()), "Pause/Resume gesture detection", move || {
+        pause_tx.send(Action::TogglePause).unwrap();
+    });
+
+    let menu: [(&str, fn() -> Action); 3] = [
+        ("Show last backup report", || Action::ShowReport),
+        ("Open log", || Action::OpenLog),
+        ("Open config", || Action::OpenConfig),
+    ];
+    for (label, action) in menu {
+        let action_tx = tx.clone();
+        tray.add_menu_item(label, move || {
+            action_tx.send(action()).unwrap();
+        }).unwrap();
+    }
+
     let quit_tx = tx.clone();
     tray.add_menu_item("Quit", move || {
         quit_tx.send(Action::Quit).unwrap();
@@ -29,6 +106,7 @@ pub fn tray_main() {
     // state
 
     let state_rx = APP_STATE.subscribe_sync();
+    let mut icon = "icon";
 
     // event handlers
 
@@ -36,9 +114,26 @@ pub fn tray_main() {
         select! {
             recv(rx) -> action => { // tray events
                  match action {
+                    Ok(Action::Backup(profile)) => {
+                        info!("tray", format!("Backup of profile {} started from tray.", profile));
+                        thread::spawn(move || backup_main(profile));
+                    },
+                    Ok(Action::TogglePause) => {
+                        let state = if APP_STATE.read() == ApplicationState::Paused { ApplicationState::Idle } else { ApplicationState::Paused };
+                        if let Err(e) = APP_STATE.change(state) {
+                            error!("tray", format!("Cannot pause or resume, {}.", e));
+                        }
+                    },
+                    Ok(Action::ShowReport) => { show_report(); },
+                    Ok(Action::OpenLog) => {
+                        if let Err(e) = open(Path::new(LOG_FILE)) {
+                            error!("tray", format!("Cannot open the log! {}", e));
+                        }
+                    },
+                    Ok(Action::OpenConfig) => { open_config(); },
                     Ok(Action::Quit) => {
                         info!("tray", "Quitted from tray.");
-                        tokio!().block_on(APP_STATE.change(ApplicationState::Quit));
+                        APP_STATE.change(ApplicationState::Quit).ok();
                         break;
                     },
                     _ => {}
@@ -47,9 +142,99 @@ pub fn tray_main() {
             recv(state_rx) -> state => {
                  match state {
                     Ok(ApplicationState::Quit) => { break; },
+                    Ok(state) => {
+                        status.set(&mut tray, status_line(&state));
+                        pause.set(&mut tray, pause_label(&state));
+                        let next_icon = match state {
+                            ApplicationState::Armed { .. } => "icon-armed",
+                            _ if state.is_backup() => "icon-busy",
+                            _ => "icon"
+                        };
+                        if next_icon != icon {
+                            icon = next_icon;
+                            tray.set_icon(IconSource::Resource(icon)).ok();
+                        }
+                    },
                     _ => {}
                 };
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// what blackout is doing, or how the last backup went when it's idle
+fn status_line(state: &ApplicationState) -> String {
+    match state {
+        ApplicationState::Armed { profile, .. } => format!("Armed: {}", profile),
+        ApplicationState::BackingUp(progress) | ApplicationState::Verifying(progress) => {
+            let percent = if progress.total_files > 0 { progress.files * 100 / progress.total_files } else { 0 };
+            let phase = if matches!(state, ApplicationState::Verifying(_)) { "Verifying" } else { "Backing up" };
+            format!("{} {}: {}%", phase, progress.profile, percent)
+        },
+        ApplicationState::Paused => "Paused".to_string(),
+        _ => match APP_STATE.last_result() {
+            Some(result) => {
+                let time = DateTime::parse_from_rfc3339(&result.finished)
+                    .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or(result.finished);
+                let outcome = match result.outcome {
+                    BackupOutcome::Success => "succeeded",
+                    BackupOutcome::PartialSuccess => "partially succeeded",
+                    BackupOutcome::Failure => "failed"
+                };
+                format!("Last backup {}: {}", time, outcome)
+            },
+            None => "No backup yet".to_string()
+        }
+    }
+}
+
+fn pause_label(state: &ApplicationState) -> String {
+    if *state == ApplicationState::Paused { "Resume gesture detection" } else { "Pause gesture detection" }.to_string()
+}
+
+/// opens the log of the last backup, or tells why it can't
+fn show_report() {
+    let message = match APP_STATE.last_result() {
+        Some(result) => match result.log {
+            Some(log) if log.is_file() => match open(&log) {
+                Ok(_) => { return; },
+                Err(e) => format!("Cannot open the report {}! {}", log.display(), e)
+            },
+            _ => result.message
+        },
+        None => "No backup was run since blackout started.".to_string()
+    };
+    alert(MessageType::Info, &message);
+}
+
+fn open_config() {
+    let Some(path) = config::path() else {
+        let path = dirs::config_dir().map(|dir| dir.join("blackout").join(config::CONFIG_FILE)).unwrap_or(PathBuf::from(config::CONFIG_FILE));
+        alert(MessageType::Info, &format!("No configuration file, blackout looks for {}.", path.display()));
+        return;
+    };
+    if let Err(e) = open(&path) {
+        error!("tray", format!("Cannot open the configuration! {}", e));
+    }
+}
+
+fn alert(kind: MessageType, text: &str) {
+    MessageDialog::new()
+        .set_type(kind)
+        .set_title("Blackout")
+        .set_text(text)
+        .show_alert()
+        .ok();
+}
+
+/// opens a file with the default application
+fn open(path: &Path) -> io::Result<()> {
+    let path = absolute(path)?;
+    #[cfg(windows)]
+    let mut command = Command::new("explorer");
+    #[cfg(not(windows))]
+    let mut command = Command::new("xdg-open");
+    command.arg(path).spawn()?;
+    Ok(())
+}
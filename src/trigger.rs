@@ -0,0 +1,206 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{after, never, Receiver, Sender, unbounded};
+use crossbeam::select;
+use lazy_static::lazy_static;
+
+use crate::{audio, config, tokio};
+use crate::config::Config;
+use crate::audio::{play_sound, SOUND_ARM, SOUND_CANCEL, SOUND_TRIGGER};
+use crate::backup;
+use crate::backup::backup_main;
+use crate::input;
+use crate::input::{InputBackend, InputEvent};
+use crate::keyboard::KeyboardTrigger;
+use crate::logger::{error, info};
+use crate::mouse::MouseTrigger;
+use crate::state::{APP_STATE, ApplicationState};
+use crate::TOKIO;
+
+/// where the backup triggering is, as seen by a trigger
+#[derive(PartialEq, Clone, Copy)]
+pub enum Phase {
+    /// nothing armed, any trigger can arm
+    Idle,
+    /// this trigger armed a backup and can confirm it
+    Armed,
+    /// another trigger armed a backup
+    Waiting,
+    /// a backup is running, whoever started it
+    Running,
+    /// the triggers are paused, the input is only tracked (e.g. the keys held down)
+    Paused,
+}
+
+/// what a trigger recognized in its input
+pub enum Signal {
+    None,
+    /// arm the backup of a profile
+    Arm(String),
+    /// start the armed backup
+    Confirm,
+    /// the confirmation was broken
+    Disarm,
+    /// cancel the running backup
+    Abort,
+}
+
+/// requests to the trigger thread from outside of it, e.g. the control socket
+/// (pausing goes through the application state)
+pub enum Control {
+    /// rebuild the triggers from the current configuration
+    Reload,
+}
+
+lazy_static! {
+    static ref CONTROL: (Sender<Control>, Receiver<Control>) = unbounded();
+}
+
+pub fn control(request: Control) {
+    CONTROL.0.send(request).ok();
+}
+
+/// a way for the user to arm, confirm and abort backups: the mouse gestures, the keyboard hotkeys
+pub trait Trigger {
+    /// name used in the logs
+    fn name(&self) -> &'static str;
+    /// handles an input event according to the phase, every trigger receives every event
+    fn feed(&mut self, event: &InputEvent, phase: Phase) -> Signal;
+    /// the partial inputs start over, called whenever the phase changes
+    fn reset(&mut self);
+}
+
+/// this thread controls the backup triggering, it listens to the input of all the triggers and plays audio
+/// a trigger arms the backup of a profile, the same trigger must confirm it, then any trigger can abort it
+/// the armed and idle phases are published to the application state, the backup publishes its own
+pub fn trigger_main() {
+    let config = config::get();
+    let mut arm_timeout = Duration::from_secs(config.gesture.arm_timeout_secs);
+
+    let input_rx = input::subscribe();
+    let state_rx = APP_STATE.subscribe_sync();
+    let control_rx = CONTROL.1.clone();
+    let backend = input::start(config.gesture.input, Duration::from_millis(config.gesture.probe_interval_ms));
+    info!("trigger", format!("Listening to the input via {}.", backend));
+
+    let mut triggers = build_triggers(&config, backend);
+
+    // trigger, profile and time of the armed backup
+    let mut armed: Option<(usize, String, Instant)> = None;
+
+    loop {
+        if APP_STATE.read() == ApplicationState::Quit {
+            break;
+        }
+        let disarm = match &armed {
+            Some((_, _, at)) => after(arm_timeout.saturating_sub(at.elapsed())),
+            None => never()
+        };
+        // the thread sleeps until there is some input
+        let event = select! {
+            recv(input_rx) -> event => match event {
+                Ok(event) => event,
+                Err(_) => { break; }
+            },
+            recv(state_rx) -> state => match state {
+                Ok(ApplicationState::Quit) => { break; },
+                Ok(ApplicationState::Armed { .. }) => { continue; },
+                _ => {
+                    // disarmed from outside, e.g. paused or a backup started from the tray
+                    if armed.take().is_some() {
+                        triggers.iter_mut().for_each(|trigger| trigger.reset());
+                    }
+                    continue;
+                }
+            },
+            recv(control_rx) -> request => {
+                match request {
+                    Ok(Control::Reload) => {
+                        if armed.take().is_some() {
+                            APP_STATE.change(ApplicationState::Idle).ok();
+                        }
+                        let config = config::get();
+                        // the input backend is started once per process
+                        if config.gesture.input != backend {
+                            error!("trigger", format!("The input backend stays {} until restarted.", backend));
+                        }
+                        arm_timeout = Duration::from_secs(config.gesture.arm_timeout_secs);
+                        triggers = build_triggers(&config, backend);
+                        info!("trigger", "Triggers reloaded.");
+                    },
+                    Err(_) => {}
+                }
+                triggers.iter_mut().for_each(|trigger| trigger.reset());
+                continue;
+            },
+            recv(disarm) -> _ => {
+                // too late for the confirmation
+                armed = None;
+                APP_STATE.change(ApplicationState::Idle).ok();
+                triggers.iter_mut().for_each(|trigger| trigger.reset());
+                tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_CANCEL));
+                info!("trigger", "Backup disarmed, the confirmation timed out.");
+                continue;
+            },
+        };
+
+        // the triggers keep seeing the input while paused, so releasing a key isn't missed
+        let state = APP_STATE.read();
+        for idx in 0..triggers.len() {
+            let phase = match &armed {
+                _ if state == ApplicationState::Paused => Phase::Paused,
+                _ if state.is_backup() => Phase::Running,
+                Some((armed, _, _)) if *armed == idx => Phase::Armed,
+                Some(_) => Phase::Waiting,
+                None => Phase::Idle
+            };
+            let name = triggers[idx].name();
+            match triggers[idx].feed(&event, phase) {
+                Signal::None => { continue; },
+                _ if phase == Phase::Paused => { continue; },
+                Signal::Arm(profile) => {
+                    if APP_STATE.change(ApplicationState::Armed { profile: profile.clone(), trigger: name.to_string() }).is_ok() {
+                        tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_ARM));
+                        info!("trigger", format!("Backup of profile {} armed by {}", profile, name));
+                        armed = Some((idx, profile, Instant::now()));
+                    }
+                },
+                Signal::Confirm => {
+                    let Some((_, profile, _)) = armed.take() else { continue; };
+                    tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_TRIGGER));
+                    info!("trigger", format!("Backup of profile {} triggered by {}!", profile, name));
+                    // spawn backup thread, the triggers can only abort it until it finishes
+                    thread::spawn(move || backup_main(profile));
+                },
+                Signal::Disarm => {
+                    // fell out or too slow
+                    armed = None;
+                    APP_STATE.change(ApplicationState::Idle).ok();
+                    tokio!().spawn(play_sound(audio::PLAYER.clone(), SOUND_CANCEL));
+                    info!("trigger", format!("Backup disarmed by {}.", name));
+                },
+                Signal::Abort => {
+                    info!("trigger", format!("Backup aborted by {}.", name));
+                    backup::cancel();
+                }
+            }
+            // the phase changed, every trigger starts over
+            triggers.iter_mut().for_each(|trigger| trigger.reset());
+            break;
+        }
+    }
+}
+
+/// the mouse trigger, and the keyboard one when hotkeys are bound and the input events are available
+fn build_triggers(config: &Config, backend: InputBackend) -> Vec<Box<dyn Trigger>> {
+    let mut triggers: Vec<Box<dyn Trigger>> = vec![Box::new(MouseTrigger::new(config))];
+    match KeyboardTrigger::new(config) {
+        Some(_) if backend == InputBackend::Polling => {
+            error!("trigger", "Hotkeys need the input events, they are disabled while polling the mouse!");
+        },
+        Some(keyboard) => { triggers.push(Box::new(keyboard)); },
+        None => {}
+    }
+    triggers
+}
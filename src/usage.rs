@@ -0,0 +1,168 @@
+use std::time::{Duration, Instant};
+
+use crate::backup::HumanReadable;
+
+/// resources used so far by the process and by the calling thread
+#[derive(Clone, Copy, Default)]
+pub struct Usage {
+    pub process_user: Duration,
+    pub process_system: Duration,
+    pub thread_user: Duration,
+    pub thread_system: Duration,
+    /// bytes moved by the read and write calls of the process, files and pipes alike
+    pub read: u64,
+    pub written: u64,
+    /// peak resident memory of the process
+    pub peak_memory: u64,
+}
+
+impl Usage {
+    #[cfg(not(windows))]
+    pub fn now() -> Self {
+        let (process_user, process_system, peak_memory) = rusage(libc::RUSAGE_SELF);
+        let (thread_user, thread_system, _) = rusage(THREAD);
+        let (read, written) = io_counters();
+        Self { process_user, process_system, thread_user, thread_system, read, written, peak_memory }
+    }
+
+    #[cfg(windows)]
+    pub fn now() -> Self {
+        use std::mem::{size_of, zeroed};
+        use winapi::shared::minwindef::{DWORD, FILETIME};
+        use winapi::um::processthreadsapi::{GetCurrentProcess, GetCurrentThread, GetProcessTimes, GetThreadTimes};
+        use winapi::um::psapi::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+        use winapi::um::winbase::GetProcessIoCounters;
+        use winapi::um::winnt::IO_COUNTERS;
+
+        // 100 ns ticks
+        let duration = |time: FILETIME| Duration::from_nanos((((time.dwHighDateTime as u64) << 32) | time.dwLowDateTime as u64) * 100);
+        let mut usage = Self::default();
+        unsafe {
+            let (mut creation, mut exit, mut kernel, mut user): (FILETIME, FILETIME, FILETIME, FILETIME) = (zeroed(), zeroed(), zeroed(), zeroed());
+            if GetProcessTimes(GetCurrentProcess(), &mut creation, &mut exit, &mut kernel, &mut user) != 0 {
+                usage.process_user = duration(user);
+                usage.process_system = duration(kernel);
+            }
+            if GetThreadTimes(GetCurrentThread(), &mut creation, &mut exit, &mut kernel, &mut user) != 0 {
+                usage.thread_user = duration(user);
+                usage.thread_system = duration(kernel);
+            }
+            let mut io: IO_COUNTERS = zeroed();
+            if GetProcessIoCounters(GetCurrentProcess(), &mut io) != 0 {
+                usage.read = io.ReadTransferCount;
+                usage.written = io.WriteTransferCount;
+            }
+            let mut memory: PROCESS_MEMORY_COUNTERS = zeroed();
+            if GetProcessMemoryInfo(GetCurrentProcess(), &mut memory, size_of::<PROCESS_MEMORY_COUNTERS>() as DWORD) != 0 {
+                usage.peak_memory = memory.PeakWorkingSetSize as u64;
+            }
+        }
+        usage
+    }
+}
+
+/// the thread's own usage is only available on linux, elsewhere it's the process one
+#[cfg(target_os = "linux")]
+const THREAD: libc::c_int = libc::RUSAGE_THREAD;
+#[cfg(all(not(windows), not(target_os = "linux")))]
+const THREAD: libc::c_int = libc::RUSAGE_SELF;
+
+/// user time, system time and peak resident memory
+#[cfg(not(windows))]
+fn rusage(who: libc::c_int) -> (Duration, Duration, u64) {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(who, &mut usage) } != 0 {
+        return Default::default();
+    }
+    let duration = |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+    // kilobytes, except on macos
+    let unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    (duration(usage.ru_utime), duration(usage.ru_stime), usage.ru_maxrss as u64 * unit)
+}
+
+/// bytes read and written by the process
+#[cfg(target_os = "linux")]
+fn io_counters() -> (u64, u64) {
+    let io = std::fs::read_to_string("/proc/self/io").unwrap_or_default();
+    let counter = |name: &str| io.lines()
+        .find_map(|line| line.strip_prefix(name))
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or_default();
+    (counter("rchar:"), counter("wchar:"))
+}
+
+/// no counters without /proc/self/io
+#[cfg(all(not(windows), not(target_os = "linux")))]
+fn io_counters() -> (u64, u64) {
+    (0, 0)
+}
+
+/// resources used by a phase of the backup
+pub struct PhaseUsage {
+    pub name: &'static str,
+    pub wall: Duration,
+    pub process_user: Duration,
+    pub process_system: Duration,
+    pub thread_user: Duration,
+    pub thread_system: Duration,
+    pub read: u64,
+    pub written: u64,
+    /// peak memory of the process at the end of the phase
+    pub peak_memory: u64,
+}
+
+/// accounts the resources of consecutive phases, must be used from a single thread since it measures that thread too
+pub struct UsageMeter {
+    phases: Vec<PhaseUsage>,
+    start: Instant,
+    usage: Usage,
+}
+
+impl UsageMeter {
+    pub fn new() -> Self {
+        Self { phases: Vec::new(), start: Instant::now(), usage: Usage::now() }
+    }
+
+    /// ends a phase, the next one starts now
+    pub fn lap(&mut self, name: &'static str) {
+        let (now, usage) = (Instant::now(), Usage::now());
+        let before = self.usage;
+        self.phases.push(PhaseUsage {
+            name,
+            wall: now - self.start,
+            process_user: usage.process_user.saturating_sub(before.process_user),
+            process_system: usage.process_system.saturating_sub(before.process_system),
+            thread_user: usage.thread_user.saturating_sub(before.thread_user),
+            thread_system: usage.thread_system.saturating_sub(before.thread_system),
+            read: usage.read.saturating_sub(before.read),
+            written: usage.written.saturating_sub(before.written),
+            peak_memory: usage.peak_memory,
+        });
+        self.start = now;
+        self.usage = usage;
+    }
+
+    /// a table of the phases so far and their total
+    pub fn report(&self) -> Vec<String> {
+        let row = |phase: &PhaseUsage| format!("{:<10} | {:>15} | {:>15} | {:>15} | {:>15} | {:>15} | {:>10} | {:>10} | {:>10}",
+            phase.name, phase.wall.human_readable(), phase.process_user.human_readable(), phase.process_system.human_readable(),
+            phase.thread_user.human_readable(), phase.thread_system.human_readable(),
+            phase.read.human_readable(), phase.written.human_readable(), phase.peak_memory.human_readable());
+        let total = PhaseUsage {
+            name: "Total",
+            wall: self.phases.iter().map(|phase| phase.wall).sum(),
+            process_user: self.phases.iter().map(|phase| phase.process_user).sum(),
+            process_system: self.phases.iter().map(|phase| phase.process_system).sum(),
+            thread_user: self.phases.iter().map(|phase| phase.thread_user).sum(),
+            thread_system: self.phases.iter().map(|phase| phase.thread_system).sum(),
+            read: self.phases.iter().map(|phase| phase.read).sum(),
+            written: self.phases.iter().map(|phase| phase.written).sum(),
+            peak_memory: self.phases.iter().map(|phase| phase.peak_memory).max().unwrap_or_default(),
+        };
+        let mut lines = vec![format!("{:<10} | {:>15} | {:>15} | {:>15} | {:>15} | {:>15} | {:>10} | {:>10} | {:>10}",
+                                     "Phase", "Wall", "CPU user", "CPU system", "Thread user", "Thread system", "Read", "Written", "Peak mem")];
+        lines.extend(self.phases.iter().map(row));
+        lines.push(row(&total));
+        lines
+    }
+}
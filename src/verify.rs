@@ -0,0 +1,87 @@
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use clap::Args;
+
+use crate::backup::{hash_file, HumanReadable};
+use crate::logger::{error, info};
+use crate::manifest;
+use crate::manifest::{Manifest, ManifestEntry, Outcome};
+use crate::restore::backup_roots;
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// Snapshot to verify
+    pub snapshot: String,
+    /// Mount point of the drive holding the snapshot, any mounted drive if missing
+    #[arg(long)]
+    pub drive: Option<PathBuf>,
+}
+
+/// re-reads the stored files of a snapshot and compares them with the hashes in the manifest,
+/// when `only_copied` is set the files whose content lives in other snapshots are left out
+/// the files held by the snapshot itself are read from its folder, which is the staging one while it is written
+/// updates the verified flag of the entries and returns the (path in snapshot, reason) of the files that failed
+pub fn verify_entries(backup_root: &Path, folder: &Path, manifest: &mut Manifest, only_copied: bool, mut progress: impl FnMut(&ManifestEntry)) -> Vec<(PathBuf, String)> {
+    let mut failed = Vec::new();
+    let snapshot = manifest.snapshot.clone();
+    for entry in manifest.entries.iter_mut().filter(|entry| entry.is_stored()) {
+        if only_copied && entry.outcome != Outcome::Copied {
+            continue;
+        }
+        progress(entry);
+        let holder = entry.holder(&snapshot);
+        let path = if holder == snapshot { folder.join(&entry.path) } else { backup_root.join(holder).join(&entry.path) };
+        let verified = match hash_file(&path) {
+            Ok(hash) if entry.hash.as_ref() == Some(&hash) => true,
+            Ok(_) => {
+                failed.push((entry.path.clone(), "content does not match the hash".to_string()));
+                false
+            },
+            Err(e) => {
+                failed.push((entry.path.clone(), format!("{}", e)));
+                false
+            }
+        };
+        entry.verified = Some(verified);
+    }
+    failed
+}
+
+/// verifies a snapshot on a drive, the result is appended to the snapshot log and recorded in the manifest
+pub fn verify_main(args: VerifyArgs) -> Result<(), Box<dyn Error>> {
+    let backup_root = backup_roots(args.drive.as_deref()).into_iter()
+        .find(|backup_root| manifest::manifest_path(backup_root, &args.snapshot).is_file())
+        .ok_or(format!("Snapshot not found: {}", args.snapshot))?;
+    let manifest_path = manifest::manifest_path(&backup_root, &args.snapshot);
+    let mut manifest = Manifest::load(&manifest_path)?;
+
+    info!("verify", format!("Verifying snapshot {} in {}", args.snapshot, backup_root.display()));
+
+    let failed = verify_entries(&backup_root, &backup_root.join(&args.snapshot), &mut manifest, false, |entry| {
+        println!("{:>10} | {}", entry.size.human_readable(), entry.path.to_string_lossy());
+    });
+    manifest.save(&manifest_path)?;
+
+    let mut backup_log = OpenOptions::new().append(true).create(true).open(manifest::log_path(&backup_root, &args.snapshot))?;
+    writeln!(backup_log, "\nVerification of {}:", Local::now().to_rfc3339())?;
+    for (path, reason) in failed.iter() {
+        writeln!(backup_log, "{} | {}", path.to_string_lossy(), reason)?;
+        eprintln!("FAILED {} | {}", path.to_string_lossy(), reason);
+    }
+
+    let verified = manifest.entries.iter().filter(|entry| entry.verified.is_some()).count();
+    let msg = format!("Verified: {} files, {} failed", verified, failed.len());
+    writeln!(backup_log, "{}", msg)?;
+    println!("\n{}", msg);
+
+    if !failed.is_empty() {
+        error!("verify", msg.clone());
+        return Err(format!("{} files failed verification!", failed.len()).into());
+    }
+    info!("verify", msg);
+    Ok(())
+}